@@ -0,0 +1,115 @@
+use std::process::ExitCode;
+
+use chain_db_rs::{AuditAccount, ChainDB, Units};
+
+const USAGE: &str = "Usage:
+  chain-db reconcile [--server <url>] --db <name> --user <user> <user_id[=initial_units]>...
+
+The password is read from the CHAIN_DB_PASSWORD environment variable.
+
+Subcommands:
+  reconcile  Replay the transfer records of the given accounts and compare them against their
+             current balances. Initial units (granted on the account creation) are optional.";
+
+// Not taken as an argument, so it doesn't show up in the shell history or the process list
+const PASSWORD_VAR: &str = "CHAIN_DB_PASSWORD";
+
+// Connection information is needed for the whole program
+fn leak(value: String) -> &'static str {
+    Box::leak(value.into_boxed_str())
+}
+
+struct ReconcileArgs {
+    server: Option<&'static str>,
+    data_base: &'static str,
+    user: &'static str,
+    password: &'static str,
+    accounts: Vec<AuditAccount>,
+}
+
+fn parse_reconcile_args(args: Vec<String>) -> Result<ReconcileArgs, String> {
+    let mut server = None;
+    let mut data_base = None;
+    let mut user = None;
+    let mut accounts = vec![];
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("Missing value of {}", name));
+
+        match arg.as_str() {
+            "--server" => server = Some(leak(value("--server")?)),
+            "--db" => data_base = Some(leak(value("--db")?)),
+            "--user" => user = Some(leak(value("--user")?)),
+            "--password" => {
+                return Err(format!(
+                    "--password is not supported, set {} instead",
+                    PASSWORD_VAR
+                ))
+            }
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            account => {
+                let (user_id, initial_units) = match account.split_once('=') {
+                    Some((user_id, units)) => {
+                        let units = units.parse::<Units>().map_err(|err| {
+                            format!("Invalid initial units of {}: {}", user_id, err)
+                        })?;
+                        (user_id, Some(units))
+                    }
+                    None => (account, None),
+                };
+                accounts.push(AuditAccount::new(user_id, initial_units));
+            }
+        }
+    }
+
+    if accounts.is_empty() {
+        return Err("No account to reconcile".to_string());
+    }
+
+    Ok(ReconcileArgs {
+        server,
+        data_base: data_base.ok_or("Missing --db")?,
+        user: user.ok_or("Missing --user")?,
+        password: leak(
+            std::env::var(PASSWORD_VAR).map_err(|_| format!("Missing {}", PASSWORD_VAR))?,
+        ),
+        accounts,
+    })
+}
+
+async fn reconcile(args: Vec<String>) -> Result<bool, String> {
+    let args = parse_reconcile_args(args)?;
+    let db = ChainDB::connect(args.server, args.data_base, args.user, args.password);
+
+    let response = db.reconcile_accounts(&args.accounts).await;
+    let Some(report) = response.data else {
+        return Err(response.error_msg);
+    };
+
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    Ok(report.is_consistent())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+
+    let result = match args.next().as_deref() {
+        Some("reconcile") => reconcile(args.collect()).await,
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        // Discrepancies were found
+        Ok(false) => ExitCode::from(1),
+        Err(error_msg) => {
+            eprintln!("{}\n\n{}", error_msg, USAGE);
+            ExitCode::from(2)
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use std::{fmt::Display, marker::PhantomData};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    features::{
+        contract::{contract_id, get_last_transaction, post_transaction},
+        log::{AppendLog, LogItem},
+        structures::BasicResponse,
+    },
+    ChainDB,
+};
+
+// Snapshot is written every time this amount of commands is stored
+const DEFAULT_SNAPSHOT_EVERY: u64 = 50;
+
+/**
+ * Domain model rebuilt from its events. `handle` decides which events a command produces and
+ * `apply` changes the state using an event
+ */
+pub trait Aggregate: Default + Serialize + DeserializeOwned {
+    type Event: Serialize + DeserializeOwned;
+    type Command;
+    type Error: Display;
+
+    fn apply(&mut self, event: &Self::Event);
+
+    fn handle(&self, command: Self::Command) -> Result<Vec<Self::Event>, Self::Error>;
+}
+
+/**
+ * State of an aggregate and the number of commands applied to it. The events of a command are
+ * stored together, as a single log entry
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VersionedAggregate<A> {
+    pub state: A,
+    pub version: u64,
+}
+
+/**
+ * Stores the events of aggregates as contract transactions. Each aggregate instance (id) has
+ * its own events log and snapshot.
+ *
+ * Commands executed through the same `ChainDB` (or its clones) are checked and stored one at a
+ * time per aggregate. Writers in other processes may still store a command at the same version.
+ */
+#[derive(Debug)]
+pub struct AggregateRepository<A> {
+    name: String,
+    db: ChainDB,
+    snapshot_every: u64,
+    _aggregate: PhantomData<fn() -> A>,
+}
+
+impl<A: Aggregate> AggregateRepository<A> {
+    /**
+     * Returns an error if the name is empty or has a `:` or `/`, as those separate the name
+     * from the aggregate ids in the contract names
+     */
+    pub fn new(db: &ChainDB, name: &str) -> Result<Self, String> {
+        if name.is_empty() || name.contains([':', '/']) {
+            return Err(format!(
+                "Invalid aggregate name {:?}: it can't be empty or have ':' or '/'",
+                name
+            ));
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            db: db.clone(),
+            snapshot_every: DEFAULT_SNAPSHOT_EVERY,
+            _aggregate: PhantomData,
+        })
+    }
+
+    /**
+     * Set how many commands are stored between snapshots. Use 0 to disable automatic snapshots
+     */
+    pub fn with_snapshot_every(mut self, commands: u64) -> Self {
+        self.snapshot_every = commands;
+        self
+    }
+
+    // Each entry has the events of one command, so they are stored (or not) all at once. The
+    // name has no '/', so the contract names of different repositories and ids can't collide
+    fn events_log(&self, id: &str) -> AppendLog<Vec<A::Event>> {
+        AppendLog::with_contract_name(&self.db, &format!("aggregate:{}/events/{}", self.name, id))
+    }
+
+    fn snapshot_contract_id(&self, id: &str) -> String {
+        contract_id(
+            &self.db,
+            &format!("aggregate:{}/snapshot/{}", self.name, id),
+        )
+    }
+
+    /**
+     * Rebuild the aggregate state from the last snapshot and the events stored after it
+     */
+    pub async fn load(&self, id: &str) -> VersionedAggregate<A> {
+        self.load_with_snapshot(id).await.0
+    }
+
+    // Aggregate and the version of its last snapshot
+    async fn load_with_snapshot(&self, id: &str) -> (VersionedAggregate<A>, u64) {
+        let snapshot =
+            get_last_transaction::<VersionedAggregate<A>>(&self.db, &self.snapshot_contract_id(id))
+                .await;
+
+        let mut aggregate = match snapshot {
+            Some(tx) => tx.data,
+            None => VersionedAggregate {
+                state: A::default(),
+                version: 0,
+            },
+        };
+        let snapshot_version = aggregate.version;
+
+        for events in self.events_since(id, aggregate.version).await {
+            for event in events.item.iter() {
+                aggregate.state.apply(event);
+            }
+            aggregate.version = events.seq + 1;
+        }
+
+        (aggregate, snapshot_version)
+    }
+
+    /**
+     * Get the events stored from the given version on, from the most old to the most recent.
+     * Each item has the events of one command
+     */
+    pub async fn events_since(&self, id: &str, version: u64) -> Vec<LogItem<Vec<A::Event>>> {
+        self.events_log(id).read(version..u64::MAX).await
+    }
+
+    /**
+     * Handle a command, store the produced events and return the updated aggregate.
+     *
+     * A snapshot is written once `snapshot_every` commands were stored after the last one. If
+     * it can't be written, the response is still a success (the events were stored) with the
+     * reason in `error_msg`, and it's tried again on the next command
+     */
+    pub async fn execute(
+        &self,
+        id: &str,
+        command: A::Command,
+    ) -> BasicResponse<VersionedAggregate<A>> {
+        let (mut aggregate, snapshot_version) = self.load_with_snapshot(id).await;
+
+        let events = match aggregate.state.handle(command) {
+            Ok(events) => events,
+            Err(err) => {
+                return BasicResponse {
+                    success: false,
+                    error_msg: err.to_string(),
+                    data: None,
+                }
+            }
+        };
+
+        // Stored only if no other writer stored events in the meantime
+        let Some(stored) = self
+            .events_log(id)
+            .append_at(aggregate.version, &events)
+            .await
+        else {
+            return BasicResponse {
+                success: false,
+                error_msg: "Aggregate was changed by another writer".to_string(),
+                data: None,
+            };
+        };
+        if !stored.success {
+            return BasicResponse {
+                success: false,
+                error_msg: stored.error_msg,
+                data: None,
+            };
+        }
+
+        for event in events.iter() {
+            aggregate.state.apply(event);
+        }
+        aggregate.version += 1;
+
+        let mut error_msg = String::new();
+        if self.snapshot_every > 0 && aggregate.version - snapshot_version >= self.snapshot_every {
+            let snapshot =
+                post_transaction(&self.db, &self.snapshot_contract_id(id), &aggregate).await;
+            if !snapshot.success {
+                error_msg = format!(
+                    "Events were stored, but the snapshot could not be written: {}",
+                    snapshot.error_msg
+                );
+            }
+        }
+
+        BasicResponse {
+            success: true,
+            error_msg,
+            data: Some(aggregate),
+        }
+    }
+
+    /**
+     * Store a snapshot of the current aggregate state
+     */
+    pub async fn snapshot(&self, id: &str) -> BasicResponse<String> {
+        let aggregate = self.load(id).await;
+        post_transaction(&self.db, &self.snapshot_contract_id(id), &aggregate).await
+    }
+}
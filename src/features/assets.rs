@@ -0,0 +1,230 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    features::{
+        log::AppendLog,
+        structures::{BasicResponse, TransferUnitsRegistry},
+        table::Table,
+        units::Units,
+    },
+    ChainDB,
+};
+
+// Prefix of the tables and logs holding the named assets of each account
+const ASSETS_PREFIX: &str = "__assets";
+
+/**
+ * Balances of the named assets of an account, e.g. gold, gems and credits
+ */
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AssetBalances {
+    pub balances: BTreeMap<String, Units>,
+}
+
+fn balances_table_name(user_id: &str) -> String {
+    format!("{}:{}", ASSETS_PREFIX, user_id)
+}
+
+async fn balances_table(db: &ChainDB, user_id: &str) -> Table<AssetBalances> {
+    Table::get(db, &balances_table_name(user_id), AssetBalances::default).await
+}
+
+fn transfers_log(db: &ChainDB, user_id: &str) -> AppendLog<TransferUnitsRegistry> {
+    AppendLog::new(db, &format!("{}:{}", ASSETS_PREFIX, user_id))
+}
+
+fn error<D>(error_msg: String) -> BasicResponse<D> {
+    BasicResponse {
+        success: false,
+        error_msg,
+        data: None,
+    }
+}
+
+async fn check_user(db: &ChainDB, user_id: &str) -> Option<BasicResponse<String>> {
+    let account = db.get_user_account_by_id(user_id).await;
+
+    if account.success {
+        None
+    } else {
+        Some(error(account.error_msg))
+    }
+}
+
+/**
+ * Get the balances of the named assets of an account
+ */
+pub async fn get_balances(db: &ChainDB, user_id: &str) -> BasicResponse<BTreeMap<String, Units>> {
+    if let Some(err) = check_user(db, user_id).await {
+        return error(err.error_msg);
+    }
+
+    BasicResponse {
+        success: true,
+        error_msg: String::new(),
+        data: Some(balances_table(db, user_id).await.table.balances),
+    }
+}
+
+/**
+ * Add units of a named asset to an account. Returns the new balance of the asset. Fails if the
+ * balances were changed by another instance at the same time, nothing is granted then
+ */
+pub async fn grant(db: &ChainDB, user_id: &str, asset: &str, units: Units) -> BasicResponse<Units> {
+    if let Some(err) = check_user(db, user_id).await {
+        return error(err.error_msg);
+    }
+
+    // Changes of this process are serialized, the ones of other instances are detected by the
+    // version check
+    let _guard = db.locks.lock(&balances_table_name(user_id)).await;
+
+    let mut table = balances_table(db, user_id).await;
+    let balance = table.table.balances.get(asset).copied().unwrap_or_default();
+
+    let Some(new_balance) = balance.checked_add(units) else {
+        return error(format!("Balance of {} is too large", asset));
+    };
+    table.table.balances.insert(asset.to_string(), new_balance);
+
+    let persisted = table.persist_checked().await;
+    if !persisted.success {
+        return error(persisted.error_msg);
+    }
+
+    BasicResponse {
+        success: true,
+        error_msg: String::new(),
+        data: Some(new_balance),
+    }
+}
+
+/**
+ * Transfer units of a named asset between users. Both balances are persisted in a single
+ * transaction, which fails if any of them was changed since it was read
+ */
+pub async fn transfer(
+    db: &ChainDB,
+    from: &str,
+    to: &str,
+    asset: &str,
+    units: Units,
+) -> BasicResponse<String> {
+    for user_id in [from, to] {
+        if let Some(err) = check_user(db, user_id).await {
+            return err;
+        }
+    }
+
+    if from == to {
+        return error("Sender and receiver must be different users".to_string());
+    }
+
+    // Always locked in the same order, so two opposite transfers can't wait for each other
+    let mut names = [balances_table_name(from), balances_table_name(to)];
+    names.sort();
+    let _first = db.locks.lock(&names[0]).await;
+    let _second = db.locks.lock(&names[1]).await;
+
+    let mut from_table = balances_table(db, from).await;
+    let mut to_table = balances_table(db, to).await;
+
+    let from_balance = from_table
+        .table
+        .balances
+        .get(asset)
+        .copied()
+        .unwrap_or_default();
+    let to_balance = to_table
+        .table
+        .balances
+        .get(asset)
+        .copied()
+        .unwrap_or_default();
+
+    let Some(from_balance) = from_balance.checked_sub(units) else {
+        return error(format!("Sender user does not have enough {}", asset));
+    };
+    let Some(to_balance) = to_balance.checked_add(units) else {
+        return error(format!("Balance of {} is too large", asset));
+    };
+
+    from_table
+        .table
+        .balances
+        .insert(asset.to_string(), from_balance);
+    to_table
+        .table
+        .balances
+        .insert(asset.to_string(), to_balance);
+
+    let commit = db
+        .transaction()
+        .stage(&from_table)
+        .stage(&to_table)
+        .commit()
+        .await;
+    if !commit.success {
+        return commit;
+    }
+
+    // Transfer records of both users
+    let mut not_recorded = vec![];
+    for user_id in [from, to] {
+        let record = TransferUnitsRegistry {
+            from: from.to_string(),
+            to: to.to_string(),
+            units,
+            asset: Some(asset.to_string()),
+            transfer_id: None,
+            memo: None,
+            timestamp: None,
+            block_height: None,
+            block_hash: None,
+        };
+        let appended = transfers_log(db, user_id).append(&record).await;
+        if !appended.success {
+            not_recorded.push(format!("{} ({})", user_id, appended.error_msg));
+        }
+    }
+
+    if !not_recorded.is_empty() {
+        return BasicResponse {
+            success: false,
+            error_msg: format!(
+                "Assets were transferred, but the transfer could not be recorded for: {}",
+                not_recorded.join(", ")
+            ),
+            data: commit.data,
+        };
+    }
+
+    commit
+}
+
+/**
+ * Fetch all the named asset transfer records of a user, from the most old to the most recent.
+ * If `asset` is given, only the transfers of this asset are returned
+ */
+pub async fn get_transfers(
+    db: &ChainDB,
+    user_id: &str,
+    asset: Option<&str>,
+) -> BasicResponse<Vec<TransferUnitsRegistry>> {
+    let log = transfers_log(db, user_id);
+    let transfers = log
+        .read(0..u64::MAX)
+        .await
+        .into_iter()
+        .map(|item| item.item)
+        .filter(|transfer| asset.is_none() || transfer.asset.as_deref() == asset)
+        .collect();
+
+    BasicResponse {
+        success: true,
+        error_msg: String::new(),
+        data: Some(transfers),
+    }
+}
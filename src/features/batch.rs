@@ -0,0 +1,292 @@
+use std::{collections::HashMap, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::{
+    features::{
+        idempotency::{settlement_status, SettlementStatus},
+        structures::BasicResponse,
+        units::Units,
+    },
+    ChainDB,
+};
+
+/**
+ * Transfer of units between two users
+ */
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Transfer {
+    pub from: String,
+    pub to: String,
+    pub units: Units,
+    #[serde(default)]
+    pub memo: Option<String>,
+}
+
+impl Transfer {
+    pub fn new(from: &str, to: &str, units: Units) -> Self {
+        Self {
+            from: from.to_string(),
+            to: to.to_string(),
+            units,
+            memo: None,
+        }
+    }
+
+    pub fn with_memo(mut self, memo: &str) -> Self {
+        self.memo = Some(memo.to_string());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatchOptions {
+    // Maximum amount of transfers being sent at the same time
+    pub concurrency: usize,
+    // If any transfer fails, the successful ones are transferred back
+    pub all_or_nothing: bool,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 8,
+            all_or_nothing: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransferResult {
+    pub transfer: Transfer,
+    pub success: bool,
+    pub error_msg: String,
+    // The transfer succeeded but was transferred back (all-or-nothing mode)
+    pub reverted: bool,
+    // The transfer was sent but its response was lost, so it may have been made. It has to be
+    // resolved with `resolve_transfer` using the id `batch:{batch_id}:{index}:forward`
+    pub outcome_unknown: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchTransferReport {
+    // Transfers are made with the transfer ids `batch:{batch_id}:{index}:forward` and reversals
+    // with `batch:{batch_id}:{index}:reverse`
+    pub batch_id: String,
+    pub results: Vec<TransferResult>,
+    // Successful transfers that were not transferred back
+    pub succeeded: usize,
+    // Transfers known not to have been made
+    pub failed: usize,
+    // Transfers whose outcome is unknown
+    pub unknown: usize,
+    pub reverted: usize,
+    pub rolled_back: bool,
+}
+
+fn error<D>(error_msg: String) -> BasicResponse<D> {
+    BasicResponse {
+        success: false,
+        error_msg,
+        data: None,
+    }
+}
+
+fn forward_id(batch_id: &str, index: usize) -> String {
+    format!("batch:{}:{}:forward", batch_id, index)
+}
+
+// Check that every sender has enough units for all of its transfers
+async fn validate(db: &ChainDB, transfers: &[Transfer]) -> Result<(), String> {
+    let mut outgoing: HashMap<&str, Units> = HashMap::new();
+
+    for transfer in transfers {
+        let total = outgoing.entry(&transfer.from).or_default();
+        *total = total.checked_add(transfer.units).ok_or(format!(
+            "Total units sent by {} is too large",
+            transfer.from
+        ))?;
+    }
+
+    for (user_id, total) in outgoing {
+        let account = db.get_user_account_by_id(user_id).await;
+        let Some(account) = account.data else {
+            return Err(format!(
+                "Sender user {} not found: {}",
+                user_id, account.error_msg
+            ));
+        };
+
+        if account.units < total {
+            return Err(format!(
+                "Sender user {} does not have enough units: has {}, needs {}",
+                user_id, account.units, total
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Transfer units for several users. Balances are checked before any transfer is sent and the
+ * transfers are sent with bounded concurrency.
+ *
+ * The server has no batch endpoint, so in the all-or-nothing mode the successful transfers are
+ * transferred back if any transfer fails. Each transfer is made with its own transfer id, and a
+ * transfer whose outcome is unknown (e.g. a timeout) may have been made: while any transfer is
+ * unknown nothing is transferred back, and `rolled_back` is false. Those transfers have to be
+ * resolved with `resolve_transfer` before deciding what to transfer back.
+ */
+pub async fn transfer_batch(
+    db: &ChainDB,
+    transfers: Vec<Transfer>,
+    options: BatchOptions,
+) -> BasicResponse<BatchTransferReport> {
+    if let Err(error_msg) = validate(db, &transfers).await {
+        return error(error_msg);
+    }
+
+    let charset = "abcdefghijklmnopqrstuvwxyz0123456789";
+    let batch_id = random_string::generate(16, charset);
+
+    let semaphore = Arc::new(Semaphore::new(options.concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+    let mut indexes = HashMap::new();
+    let mut results: Vec<Option<TransferResult>> = Vec::with_capacity(transfers.len());
+
+    for (index, transfer) in transfers.iter().cloned().enumerate() {
+        let db = db.clone();
+        let semaphore = semaphore.clone();
+        let transfer_id = forward_id(&batch_id, index);
+        results.push(None);
+
+        let task = tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await.unwrap();
+            let response = db
+                .transfer_units_with_id(
+                    &transfer_id,
+                    &transfer.from,
+                    &transfer.to,
+                    transfer.units,
+                    transfer.memo.clone(),
+                )
+                .await;
+
+            // A failure is known only once the id is recorded as failed (or was never claimed)
+            let outcome_unknown = !response.success
+                && settlement_status(&db, &transfer_id).await == Some(SettlementStatus::Pending);
+
+            TransferResult {
+                transfer,
+                success: response.success,
+                error_msg: response.error_msg,
+                reverted: false,
+                outcome_unknown,
+            }
+        });
+        indexes.insert(task.id(), index);
+    }
+
+    while let Some(task) = tasks.join_next_with_id().await {
+        match task {
+            Ok((id, result)) => results[indexes[&id]] = Some(result),
+            Err(join_error) => {
+                let index = indexes[&join_error.id()];
+                // The task may have stopped after sending the transfer
+                let outcome_unknown = settlement_status(db, &forward_id(&batch_id, index)).await
+                    == Some(SettlementStatus::Pending);
+
+                results[index] = Some(TransferResult {
+                    transfer: transfers[index].clone(),
+                    success: false,
+                    error_msg: format!("Transfer task failed: {}", join_error),
+                    reverted: false,
+                    outcome_unknown,
+                });
+            }
+        }
+    }
+    let mut results: Vec<TransferResult> = results.into_iter().flatten().collect();
+
+    let unknown = results
+        .iter()
+        .filter(|result| result.outcome_unknown)
+        .count();
+    let failed = results
+        .iter()
+        .filter(|result| !result.success && !result.outcome_unknown)
+        .count();
+    let rolled_back = options.all_or_nothing && failed > 0 && unknown == 0;
+
+    if rolled_back {
+        for (index, result) in results.iter_mut().enumerate() {
+            if !result.success {
+                continue;
+            }
+
+            // With a transfer id, retrying a reversal with an unknown outcome never sends it twice
+            let transfer = &result.transfer;
+            let reverse = db
+                .without_transfer_policies()
+                .transfer_units_with_id(
+                    &format!("batch:{}:{}:reverse", batch_id, index),
+                    &transfer.to,
+                    &transfer.from,
+                    transfer.units,
+                    None,
+                )
+                .await;
+
+            if reverse.success {
+                result.reverted = true;
+            } else {
+                result.error_msg = format!("Transfer could not be reversed: {}", reverse.error_msg);
+            }
+        }
+    }
+
+    let reverted = results.iter().filter(|result| result.reverted).count();
+    let report = BatchTransferReport {
+        batch_id,
+        succeeded: results.len() - failed - unknown - reverted,
+        failed,
+        unknown,
+        reverted,
+        rolled_back,
+        results,
+    };
+
+    let error_msg = if unknown > 0 {
+        let transfer_ids: Vec<String> = report
+            .results
+            .iter()
+            .enumerate()
+            .filter(|(_, result)| result.outcome_unknown)
+            .map(|(index, _)| forward_id(&report.batch_id, index))
+            .collect();
+        let not_reversed = if options.all_or_nothing && failed > 0 {
+            ", nothing was transferred back"
+        } else {
+            ""
+        };
+        format!(
+            "{} transfers failed and {} have an unknown outcome{}. Resolve them with `resolve_transfer`: {}",
+            failed,
+            unknown,
+            not_reversed,
+            transfer_ids.join(", ")
+        )
+    } else if failed > 0 {
+        format!("{} transfers failed", failed)
+    } else {
+        String::new()
+    };
+
+    BasicResponse {
+        success: failed == 0 && unknown == 0,
+        error_msg,
+        data: Some(report),
+    }
+}
@@ -9,4 +9,4 @@ pub const GET_USER_ACCOUNT_BY_ID: &'static str = "/get_user_account_by_id";
 pub const TRANSFER_UNITS: &'static str = "/transfer_units";
 pub const GET_TRANSFER_BY_USER_ID: &'static str = "/get_transfer_by_user_id";
 pub const GET_ALL_TRANSFER_BY_USER_ID: &'static str = "/get_all_transfers_by_user_id";
-pub const CHECK_USER_NAME: &'static str = "/check_user_name";
\ No newline at end of file
+pub const CHECK_USER_NAME: &'static str = "/check_user_name";
@@ -0,0 +1,124 @@
+use reqwest::header::CONTENT_TYPE;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    features::{
+        constants::{CONTRACT_PAYLOAD, CONTRACT_TRANSACTION, CONTRACT_TRANSACTIONS_PAYLOAD},
+        structures::{BasicResponse, ContractTransactionData, TransactionType},
+    },
+    ChainDB,
+};
+
+/**
+ * Create the contract id for a named contract inside the connected database
+ */
+pub fn contract_id(db: &ChainDB, contract_name: &str) -> String {
+    db.access.parse(db.name.to_string(), contract_name.to_string())
+}
+
+/**
+ * Fetch the last transaction of a contract. Returns `None` if nothing was stored yet
+ */
+pub async fn get_last_transaction<Model: DeserializeOwned>(
+    db: &ChainDB,
+    contract_id: &str,
+) -> Option<ContractTransactionData<Model>> {
+    let url = format!(
+        "{api}/{path}/{contract_id}/{db_access_key}",
+        api = db.api,
+        path = CONTRACT_PAYLOAD,
+        contract_id = contract_id,
+        db_access_key = db.access_key,
+    );
+    let res_json = reqwest::get(url).await.unwrap().text().await.unwrap();
+
+    // Check if any info was found
+    let data_json_check = serde_json::from_str::<Value>(&res_json).unwrap();
+    if data_json_check.get("tx_type").and_then(Value::as_str) == Some("NONE") {
+        return None;
+    }
+
+    Some(serde_json::from_str(&res_json).unwrap())
+}
+
+/**
+ * Fetch the transactions of a contract, from the most recent to the most old, in a range of depth
+ */
+pub async fn get_transactions<Model: DeserializeOwned>(
+    db: &ChainDB,
+    contract_id: &str,
+    depth: u64,
+) -> Vec<ContractTransactionData<Model>> {
+    let url = format!(
+        "{api}{route}/{contract_id}/{db_key}/{depth}",
+        api = db.api,
+        route = CONTRACT_TRANSACTIONS_PAYLOAD,
+        contract_id = contract_id,
+        db_key = db.access_key,
+        depth = depth
+    );
+
+    let res_json = reqwest::get(url).await.expect("Something went wrong!").text().await.unwrap();
+    let data_json_check = serde_json::from_str::<Value>(&res_json).unwrap();
+    let data_arr = data_json_check.as_array().unwrap();
+
+    // Return empty if theres no data
+    let no_data = data_arr.is_empty()
+        || (data_arr.len() == 1
+            && data_arr[0].get("tx_type").and_then(Value::as_str) == Some("NONE"));
+    if no_data {
+        return vec![];
+    }
+
+    serde_json::from_str(&res_json).unwrap()
+}
+
+/**
+ * Store a new contract transaction with the given data
+ */
+pub async fn post_transaction<Model: Serialize>(
+    db: &ChainDB,
+    contract_id: &str,
+    data: &Model,
+) -> BasicResponse<String> {
+    let url = format!("{api}{route}", api = db.api, route = CONTRACT_TRANSACTION);
+
+    let contract_data = serde_json::to_string(data).unwrap();
+
+    let body = json!({
+        "tx_type": TransactionType::CONTRACT,
+        "contract_id": contract_id,
+        "db_access_key": &db.access_key,
+        "data": &contract_data
+    });
+
+    let json_body = serde_json::to_string(&body).unwrap();
+
+    let response = db
+        .client
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(json_body)
+        .send()
+        .await;
+
+    match response {
+        Ok(response) => {
+            let success = response.status().is_success();
+            let text = response.text().await.unwrap_or_default();
+
+            // Use the server response if it follows the basic structure
+            serde_json::from_str::<BasicResponse<String>>(&text).unwrap_or(BasicResponse {
+                success,
+                error_msg: if success { String::new() } else { text },
+                data: None,
+            })
+        }
+        Err(err) => BasicResponse {
+            success: false,
+            error_msg: err.to_string(),
+            data: None,
+        },
+    }
+}
@@ -0,0 +1,363 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    features::{
+        contract::{contract_id, post_transaction},
+        kv::KeyValueStore,
+        profile::PROFILE_TABLE,
+        recovery::update_recovery_account,
+        session::{register_user_table, user_table_name, user_tables},
+        structures::{BasicResponse, SignedUserAccount},
+        table::Table,
+        units::Units,
+        validation::normalize_user_name,
+    },
+    ChainDB,
+};
+
+// Key-value namespace keeping the password changes being migrated, keyed by user name
+const PASSWORD_CHANGES_NAMESPACE: &str = "__password_changes";
+// Key-value namespace keeping the name on the server of the users whose password was changed,
+// keyed by user name
+const ACCOUNT_NAMES_NAMESPACE: &str = "__account_names";
+
+/**
+ * Account of a user on the server after a password change. The server can't change passwords,
+ * so each change creates a new account named `{user_name}~{suffix}`, which `get_user_account`
+ * signs in to instead
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct AccountName {
+    // Name of the first account of the user, as it was created
+    pub user_name: String,
+    pub server_name: String,
+}
+
+/**
+ * Password change being migrated. Only a salted hash of the new password is kept, to check that
+ * a resume is made with it
+ */
+#[derive(Debug, Serialize, Deserialize)]
+struct PasswordChange {
+    old_id: String,
+    // Account created with the new password
+    name: AccountName,
+    salt: String,
+    new_password_hash: String,
+}
+
+impl PasswordChange {
+    fn new(old_id: &str, name: AccountName, new_password: &str) -> Self {
+        let salt = random_string::generate(16, "abcdefghijklmnopqrstuvwxyz0123456789");
+        Self {
+            old_id: old_id.to_string(),
+            name,
+            new_password_hash: sha256::digest(format!("{}{}", salt, new_password)),
+            salt,
+        }
+    }
+
+    fn is_new_password(&self, password: &str) -> bool {
+        sha256::digest(format!("{}{}", self.salt, password)) == self.new_password_hash
+    }
+}
+
+// Password changes are keyed by the normalized name, so the same name typed in different ways
+// finds the same change
+fn change_key(user_name: &str) -> String {
+    normalize_user_name(user_name)
+}
+
+fn migrations(db: &ChainDB) -> KeyValueStore {
+    KeyValueStore::internal(db, PASSWORD_CHANGES_NAMESPACE)
+}
+
+fn account_names(db: &ChainDB) -> KeyValueStore {
+    KeyValueStore::internal(db, ACCOUNT_NAMES_NAMESPACE)
+}
+
+fn error<D>(error_msg: String) -> BasicResponse<D> {
+    BasicResponse {
+        success: false,
+        error_msg,
+        data: None,
+    }
+}
+
+/**
+ * Account on the server of a user whose password was changed. `None` if it never was
+ */
+pub(crate) async fn account_name(db: &ChainDB, user_name: &str) -> Option<AccountName> {
+    account_names(db).get(&change_key(user_name)).await
+}
+
+// Name on the server of the next account of a user. A random suffix is used, so an account
+// left by an interrupted change never takes the name of the next one
+async fn next_account_name(
+    db: &ChainDB,
+    user_name: &str,
+    old_id: &str,
+) -> Result<AccountName, String> {
+    let user_name = match account_name(db, user_name).await {
+        Some(name) => name.user_name,
+        // The server keeps the name as the account was created
+        None => {
+            let account = db.get_user_account_by_id(old_id).await;
+            let Some(account) = account.data else {
+                return Err(account.error_msg);
+            };
+            account.user_name
+        }
+    };
+
+    let suffix = random_string::generate(8, "abcdefghijklmnopqrstuvwxyz0123456789");
+    Ok(AccountName {
+        server_name: format!("{}~{}", user_name, suffix),
+        user_name,
+    })
+}
+
+// Copy the tables owned by the old account to the new one. The profile is always copied, even
+// if it was written without being registered
+async fn migrate_tables(db: &ChainDB, old_id: &str, new_id: &str) -> Result<(), String> {
+    let mut table_names = user_tables(db, old_id).await;
+    if !table_names.iter().any(|name| name == PROFILE_TABLE) {
+        table_names.push(PROFILE_TABLE.to_string());
+    }
+
+    for table_name in table_names {
+        let old_table = user_table_name(old_id, &table_name);
+        let table = Table::get(db, &old_table, || Value::Null).await;
+
+        if !table.table.is_null() {
+            let new_table = contract_id(db, &user_table_name(new_id, &table_name));
+            let copied = post_transaction(db, &new_table, &table.table).await;
+            if !copied.success {
+                return Err(format!(
+                    "Table {} could not be migrated: {}",
+                    table_name, copied.error_msg
+                ));
+            }
+        }
+
+        let registered = register_user_table(db, new_id, &table_name).await;
+        if !registered.success {
+            return Err(registered.error_msg);
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Change the password of a user. The current password is verified first.
+ *
+ * The server has no route to change a password, so a new account is created with the new
+ * password, the units and the tables owned by the user (see `UserSession::get_table`) are moved
+ * to it, and the old account is retired: the user name signs in to the new account from then
+ * on. The old account is left on the server without units.
+ *
+ * If the migration is interrupted, calling it again with the new password as `password`
+ * resumes it. Any other password is rejected until the migration is finished.
+ */
+pub async fn change_password(
+    db: &ChainDB,
+    user_name: &str,
+    password: &str,
+    new_password: &str,
+) -> BasicResponse<SignedUserAccount> {
+    // Resume the migration of a password already changed
+    if let Some(pending) = migrations(db)
+        .get::<PasswordChange>(&change_key(user_name))
+        .await
+    {
+        return resume(db, user_name, password, pending).await;
+    }
+
+    let account = db.get_user_account(user_name, password).await;
+    let Some(account) = account.data else {
+        return error(account.error_msg);
+    };
+
+    if password == new_password {
+        return error("New password must be different from the current one".to_string());
+    }
+    if let Err(errors) = db
+        .validation_rules()
+        .validate_password(new_password, Some(user_name))
+    {
+        return error(errors.to_string());
+    }
+
+    create_account(db, user_name, &account.id, new_password).await
+}
+
+// Create the account with the new password and migrate the old one to it
+async fn create_account(
+    db: &ChainDB,
+    user_name: &str,
+    old_id: &str,
+    new_password: &str,
+) -> BasicResponse<SignedUserAccount> {
+    let name = match next_account_name(db, user_name, old_id).await {
+        Ok(name) => name,
+        Err(error_msg) => return error(error_msg),
+    };
+    let change = PasswordChange::new(old_id, name, new_password);
+
+    let pending = migrations(db).set(&change_key(user_name), &change).await;
+    if !pending.success {
+        return error(pending.error_msg);
+    }
+
+    let created = db
+        .post_user_account(&change.name.server_name, new_password, None, None)
+        .await;
+    if let Some(new_account) = created.data {
+        return migrate_account(db, user_name, &change, new_account).await;
+    }
+
+    // The account may have been created even if its response was lost
+    let new_account = db
+        .fetch_user_account(&change.name.server_name, new_password)
+        .await;
+    let Some(new_account) = new_account.data else {
+        let _ = migrations(db).delete(&change_key(user_name)).await;
+        return created;
+    };
+
+    migrate_account(db, user_name, &change, new_account).await
+}
+
+/**
+ * Set a new password for a user without its current one, e.g. after one of its recovery codes
+ * was verified. The server can't reset passwords, so the account is migrated to a new one as in
+ * `change_password`: moving the units of the old account only needs the database access key
+ */
+pub(crate) async fn reset_password(
+    db: &ChainDB,
+    user_name: &str,
+    old_id: &str,
+    new_password: &str,
+) -> BasicResponse<SignedUserAccount> {
+    if let Some(pending) = migrations(db)
+        .get::<PasswordChange>(&change_key(user_name))
+        .await
+    {
+        return resume(db, user_name, new_password, pending).await;
+    }
+
+    if let Err(errors) = db
+        .validation_rules()
+        .validate_password(new_password, Some(user_name))
+    {
+        return error(errors.to_string());
+    }
+
+    create_account(db, user_name, old_id, new_password).await
+}
+
+async fn resume(
+    db: &ChainDB,
+    user_name: &str,
+    password: &str,
+    pending: PasswordChange,
+) -> BasicResponse<SignedUserAccount> {
+    let retry = "A password change of this user is being migrated, call it again with the new password to finish it";
+
+    // The old password may still sign in to the old account
+    if !pending.is_new_password(password) {
+        return error(retry.to_string());
+    }
+
+    let account = db
+        .fetch_user_account(&pending.name.server_name, password)
+        .await;
+    let Some(account) = account.data else {
+        // Interrupted before the new account was created
+        let _ = migrations(db).delete(&change_key(user_name)).await;
+        return error(format!(
+            "Password was not changed, call it again with the current password: {}",
+            account.error_msg
+        ));
+    };
+
+    migrate_account(db, user_name, &pending, account).await
+}
+
+// Move the tables and the units of the old account to the new one
+async fn move_account(db: &ChainDB, old_id: &str, new_id: &str) -> Result<(), String> {
+    if old_id == new_id {
+        return Ok(());
+    }
+
+    migrate_tables(db, old_id, new_id).await?;
+
+    let old_balance = db
+        .get_user_account_by_id(old_id)
+        .await
+        .data
+        .map(|account| account.units)
+        .unwrap_or(Units::ZERO);
+
+    if old_balance > Units::ZERO {
+        let moved = db
+            .without_transfer_policies()
+            .transfer_units_with_id(
+                &format!("password_change:{}:{}", old_id, new_id),
+                old_id,
+                new_id,
+                old_balance,
+                Some("Password change".to_string()),
+            )
+            .await;
+
+        if !moved.success {
+            return Err(format!("Units could not be migrated: {}", moved.error_msg));
+        }
+    }
+
+    Ok(())
+}
+
+async fn migrate_account(
+    db: &ChainDB,
+    user_name: &str,
+    change: &PasswordChange,
+    new_account: SignedUserAccount,
+) -> BasicResponse<SignedUserAccount> {
+    // Already switched if the migration was interrupted right before finishing it. The tables
+    // are not copied again, the new account may have changed them
+    let switched = account_name(db, user_name)
+        .await
+        .is_some_and(|name| name.server_name == change.name.server_name);
+
+    if !switched {
+        if let Err(error_msg) = move_account(db, &change.old_id, &new_account.id).await {
+            return error(error_msg);
+        }
+
+        // Recovery codes must reset the new account
+        update_recovery_account(db, user_name, &new_account.id).await;
+
+        // Retire the old account, the user name signs in to the new one from now on
+        let stored = account_names(db)
+            .set(&change_key(user_name), &change.name)
+            .await;
+        if !stored.success {
+            return error(format!(
+                "Account could not be switched, call it again with the new password: {}",
+                stored.error_msg
+            ));
+        }
+    }
+
+    let _ = migrations(db).delete(&change_key(user_name)).await;
+
+    let mut account = db.get_user_account_by_id(&new_account.id).await;
+    if let Some(account) = account.data.as_mut() {
+        account.user_name = change.name.user_name.clone();
+    }
+    account
+}
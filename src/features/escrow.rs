@@ -0,0 +1,340 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    features::{
+        idempotency::{settlement_status, SettlementStatus},
+        kv::KeyValueStore,
+        structures::BasicResponse,
+        units::Units,
+    },
+    ChainDB,
+};
+
+// Prefix of the key-value namespaces where the holds of each escrow account are recorded
+const ESCROW_PREFIX: &str = "__escrow";
+// A pending hold whose transfer was not sent yet may still be in progress, so it's set as failed
+// only once it's older than this
+const RESOLVE_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum HoldStatus {
+    // Units are being moved from the buyer to the escrow account
+    Pending,
+    Open,
+    // Units are being moved from the escrow account to the seller
+    Releasing,
+    // Units are being moved from the escrow account back to the buyer
+    Refunding,
+    Released,
+    Refunded,
+    // The units could not be moved to the escrow account
+    Failed,
+}
+
+/**
+ * Units of a buyer locked in an escrow account until they are released to the seller or
+ * refunded to the buyer
+ */
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Hold {
+    pub id: String,
+    pub buyer: String,
+    pub seller: String,
+    pub units: Units,
+    // Terms agreed by the buyer and the seller, e.g. an order id or a description
+    pub agreement: String,
+    pub created_at: u64,
+    // Unix timestamp (seconds) after which the hold can't be released anymore
+    pub expires_at: Option<u64>,
+    pub status: HoldStatus,
+}
+
+impl Hold {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= now())
+    }
+
+    fn transfer_id(&self, step: &str) -> String {
+        format!("escrow:{}:{}", self.id, step)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn error<D>(error_msg: String) -> BasicResponse<D> {
+    BasicResponse {
+        success: false,
+        error_msg,
+        data: None,
+    }
+}
+
+/**
+ * Escrow handle. Holds are recorded in a key-value namespace of the escrow account and every
+ * transfer is made with a transfer id derived from the hold, so retrying an interrupted step
+ * never moves the units twice
+ */
+#[derive(Clone, Debug)]
+pub struct Escrow {
+    escrow_account: String,
+    holds: KeyValueStore,
+    db: ChainDB,
+}
+
+impl Escrow {
+    /**
+     * `escrow_account` is the id of the user account holding the locked units. Returns an
+     * error if it has a `:` or `/`, which account ids never have
+     */
+    pub fn new(db: &ChainDB, escrow_account: &str) -> Result<Self, String> {
+        let namespace = format!("{}_{}", ESCROW_PREFIX, escrow_account);
+        let holds = KeyValueStore::new(db, &namespace)
+            .map_err(|_| format!("Invalid escrow account {:?}", escrow_account))?;
+
+        Ok(Self {
+            escrow_account: escrow_account.to_string(),
+            holds,
+            db: db.clone(),
+        })
+    }
+
+    /**
+     * Move units from the buyer to the escrow account. The hold expires at the given unix
+     * timestamp (seconds), if any
+     */
+    pub async fn create_hold(
+        &self,
+        buyer: &str,
+        seller: &str,
+        units: Units,
+        agreement: &str,
+        expires_at: Option<u64>,
+    ) -> BasicResponse<Hold> {
+        if let Some(err) = self.check_user(seller).await {
+            return err;
+        }
+
+        let charset = "abcdefghijklmnopqrstuvwxyz0123456789";
+        let mut hold = Hold {
+            id: random_string::generate(16, charset),
+            buyer: buyer.to_string(),
+            seller: seller.to_string(),
+            units,
+            agreement: agreement.to_string(),
+            created_at: now(),
+            expires_at,
+            status: HoldStatus::Pending,
+        };
+
+        let pending = self.holds.set(&hold.id, &hold).await;
+        if !pending.success {
+            return error(pending.error_msg);
+        }
+
+        let transfer = self
+            .db
+            .transfer_units_with_id(
+                &hold.transfer_id("hold"),
+                buyer,
+                &self.escrow_account,
+                units,
+                Some(format!("Escrow hold {}", hold.id)),
+            )
+            .await;
+
+        if !transfer.success {
+            // An unknown outcome leaves the hold pending, the units may have been moved
+            let known_failed = settlement_status(&self.db, &hold.transfer_id("hold")).await
+                != Some(SettlementStatus::Pending);
+            if known_failed {
+                self.set_status(&mut hold, HoldStatus::Failed).await;
+            }
+            return error(transfer.error_msg);
+        }
+
+        self.set_status(&mut hold, HoldStatus::Open).await;
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(hold),
+        }
+    }
+
+    /**
+     * Get a hold. A hold interrupted in the middle of a step is resolved first. A hold whose
+     * transfer has an unknown outcome stays pending until the transfer
+     * `escrow:{hold_id}:hold` is resolved with `resolve_transfer`
+     */
+    pub async fn get_hold(&self, hold_id: &str) -> BasicResponse<Hold> {
+        let Some(mut hold) = self.holds.get::<Hold>(hold_id).await else {
+            return error("Hold not found".to_string());
+        };
+
+        self.resolve(&mut hold).await;
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(hold),
+        }
+    }
+
+    /**
+     * Release the units of an open hold to the seller. Expired holds can only be refunded
+     */
+    pub async fn release(&self, hold_id: &str) -> BasicResponse<Hold> {
+        let hold = self.get_hold(hold_id).await;
+        let Some(hold) = hold.data else {
+            return hold;
+        };
+
+        if hold.status == HoldStatus::Open && hold.is_expired() {
+            return error("Hold has expired".to_string());
+        }
+
+        self.close(hold, HoldStatus::Releasing).await
+    }
+
+    /**
+     * Refund the units of an open hold to the buyer
+     */
+    pub async fn refund(&self, hold_id: &str) -> BasicResponse<Hold> {
+        let hold = self.get_hold(hold_id).await;
+        let Some(hold) = hold.data else {
+            return hold;
+        };
+
+        self.close(hold, HoldStatus::Refunding).await
+    }
+
+    /**
+     * List the holds whose units are still locked in the escrow account
+     */
+    pub async fn open_holds(&self) -> Vec<Hold> {
+        let mut open = vec![];
+
+        for id in self.holds.keys().await {
+            if let Some(hold) = self.get_hold(&id).await.data {
+                if hold.status == HoldStatus::Open {
+                    open.push(hold);
+                }
+            }
+        }
+
+        open
+    }
+
+    /**
+     * Refund all the expired holds. Returns the refunded holds
+     */
+    pub async fn refund_expired(&self) -> BasicResponse<Vec<Hold>> {
+        let mut refunded = vec![];
+
+        for hold in self.open_holds().await {
+            if !hold.is_expired() {
+                continue;
+            }
+
+            let refund = self.close(hold, HoldStatus::Refunding).await;
+            let Some(hold) = refund.data else {
+                return error(refund.error_msg);
+            };
+            refunded.push(hold);
+        }
+
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(refunded),
+        }
+    }
+
+    async fn check_user(&self, user_id: &str) -> Option<BasicResponse<Hold>> {
+        let account = self.db.get_user_account_by_id(user_id).await;
+
+        if account.success {
+            None
+        } else {
+            Some(error(account.error_msg))
+        }
+    }
+
+    async fn set_status(&self, hold: &mut Hold, status: HoldStatus) {
+        hold.status = status;
+        let _ = self.holds.set(&hold.id, hold).await;
+    }
+
+    // Finish a pending hold whose response was lost, e.g. the process stopped. It's set as failed
+    // only when its transfer is known not to have been made
+    async fn resolve(&self, hold: &mut Hold) {
+        if hold.status != HoldStatus::Pending {
+            return;
+        }
+
+        let status = match settlement_status(&self.db, &hold.transfer_id("hold")).await {
+            Some(SettlementStatus::Settled) => HoldStatus::Open,
+            Some(SettlementStatus::Failed) => HoldStatus::Failed,
+            // Outcome unknown, it has to be resolved with `resolve_transfer`
+            Some(SettlementStatus::Pending) => return,
+            // Never sent, unless the hold is still being created
+            None if now().saturating_sub(hold.created_at) >= RESOLVE_AFTER.as_secs() => {
+                HoldStatus::Failed
+            }
+            None => return,
+        };
+        self.set_status(hold, status).await;
+    }
+
+    // Move the units of the hold out of the escrow account. A hold already being released or
+    // refunded is resumed in the same direction
+    async fn close(&self, mut hold: Hold, closing: HoldStatus) -> BasicResponse<Hold> {
+        if hold.status == HoldStatus::Open {
+            let expected = hold.clone();
+            hold.status = closing.clone();
+
+            let claimed = self
+                .holds
+                .compare_and_set(&hold.id, Some(&expected), &hold)
+                .await;
+            if !claimed.success {
+                return error("Hold was changed by another writer".to_string());
+            }
+        } else if hold.status != closing {
+            return error(format!("Hold is not open ({:?})", hold.status));
+        }
+
+        let (step, to, closed) = match closing {
+            HoldStatus::Releasing => ("release", hold.seller.clone(), HoldStatus::Released),
+            _ => ("refund", hold.buyer.clone(), HoldStatus::Refunded),
+        };
+
+        let transfer = self
+            .db
+            .transfer_units_with_id(
+                &hold.transfer_id(step),
+                &self.escrow_account,
+                &to,
+                hold.units,
+                Some(format!("Escrow {} {}", step, hold.id)),
+            )
+            .await;
+
+        if !transfer.success {
+            return error(transfer.error_msg);
+        }
+
+        self.set_status(&mut hold, closed).await;
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(hold),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    features::{
+        kv::KeyValueStore,
+        structures::{BasicResponse, TransferUnitsRegistry},
+        units::Units,
+    },
+    ChainDB,
+};
+
+// Key-value namespace where the transfers made with an id are recorded
+const TRANSFER_IDS_NAMESPACE: &str = "__transfer_ids";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum SettlementStatus {
+    // The id was claimed and the transfer sent. If it stays pending, its outcome is unknown
+    Pending,
+    Settled,
+    // Rejected by the server (or a policy), the id can be used again
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct TransferSettlement {
+    status: SettlementStatus,
+    transfer: TransferUnitsRegistry,
+}
+
+fn settlements(db: &ChainDB) -> KeyValueStore {
+    KeyValueStore::internal(db, TRANSFER_IDS_NAMESPACE)
+}
+
+fn error<D>(error_msg: String) -> BasicResponse<D> {
+    BasicResponse {
+        success: false,
+        error_msg,
+        data: None,
+    }
+}
+
+fn unknown_outcome<D>(transfer_id: &str) -> BasicResponse<D> {
+    error(format!(
+        "Transfer {} was sent but its outcome is unknown. Check the transfer records and call `resolve_transfer`",
+        transfer_id
+    ))
+}
+
+/**
+ * Status of the transfer made with the given id. `None` if the id was never used
+ */
+pub(crate) async fn settlement_status(db: &ChainDB, transfer_id: &str) -> Option<SettlementStatus> {
+    settlements(db)
+        .get::<TransferSettlement>(transfer_id)
+        .await
+        .map(|settlement| settlement.status)
+}
+
+/**
+ * Transfer units using a client generated transfer id. If a transfer with the same id was
+ * already settled, nothing is transferred.
+ *
+ * The server doesn't keep transfer ids, so the id is claimed in a key-value store before the
+ * transfer is sent (at most once). If the response is lost (e.g. a timeout), the transfer stays
+ * pending and is never sent again automatically: the outcome has to be checked and recorded
+ * with `resolve_transfer`.
+ *
+ * Calls made through the same `ChainDB` (or its clones) are serialized by id. The claim made
+ * by other processes is checked on the client, so two processes using the same id at the same
+ * moment may both send it.
+ */
+pub async fn transfer_with_id(
+    db: &ChainDB,
+    transfer_id: &str,
+    from: &str,
+    to: &str,
+    units: Units,
+    memo: Option<String>,
+) -> BasicResponse<String> {
+    let _guard = db.locks.lock(&format!("transfer_id:{}", transfer_id)).await;
+    let store = settlements(db);
+
+    let current = store.get::<TransferSettlement>(transfer_id).await;
+    if let Some(settlement) = current.as_ref() {
+        let transfer = &settlement.transfer;
+        if transfer.from != from || transfer.to != to || transfer.units != units {
+            return error("Transfer id was already used for a different transfer".to_string());
+        }
+
+        match settlement.status {
+            SettlementStatus::Settled => {
+                return BasicResponse {
+                    success: true,
+                    error_msg: String::new(),
+                    data: Some(transfer_id.to_string()),
+                }
+            }
+            SettlementStatus::Pending => return unknown_outcome(transfer_id),
+            SettlementStatus::Failed => {}
+        }
+    }
+
+    let mut settlement = TransferSettlement {
+        status: SettlementStatus::Pending,
+        transfer: TransferUnitsRegistry {
+            from: from.to_string(),
+            to: to.to_string(),
+            units,
+            asset: None,
+            transfer_id: Some(transfer_id.to_string()),
+            memo: memo.clone(),
+            timestamp: None,
+            block_height: None,
+            block_hash: None,
+        },
+    };
+
+    let claimed = store
+        .compare_and_set(transfer_id, current.as_ref(), &settlement)
+        .await;
+    if !claimed.success {
+        return error(format!(
+            "Transfer id {} could not be claimed: {}",
+            transfer_id, claimed.error_msg
+        ));
+    }
+
+    let response = match db
+        .post_transfer(from, to, units, memo, Some(transfer_id))
+        .await
+    {
+        Ok(response) => response,
+        // Kept as pending, the transfer may have been made
+        Err(_) => return unknown_outcome(transfer_id),
+    };
+
+    settlement.status = if response.success {
+        SettlementStatus::Settled
+    } else {
+        SettlementStatus::Failed
+    };
+    let stored = store.set(transfer_id, &settlement).await;
+    if response.success && !stored.success {
+        return error(format!(
+            "Transfer {} was made, but it could not be recorded as settled: {}",
+            transfer_id, stored.error_msg
+        ));
+    }
+
+    response
+}
+
+/**
+ * Record the outcome of a pending transfer, checked by other means (e.g. the balances or the
+ * transfer records). A transfer resolved as not settled can be sent again with the same id
+ */
+pub async fn resolve_transfer(
+    db: &ChainDB,
+    transfer_id: &str,
+    settled: bool,
+) -> BasicResponse<String> {
+    let _guard = db.locks.lock(&format!("transfer_id:{}", transfer_id)).await;
+    let store = settlements(db);
+
+    let Some(mut settlement) = store.get::<TransferSettlement>(transfer_id).await else {
+        return error("Transfer not found".to_string());
+    };
+    if settlement.status != SettlementStatus::Pending {
+        return error(format!("Transfer {} is not pending", transfer_id));
+    }
+
+    settlement.status = if settled {
+        SettlementStatus::Settled
+    } else {
+        SettlementStatus::Failed
+    };
+    store.set(transfer_id, &settlement).await
+}
+
+/**
+ * Get the transfer made with the given transfer id, if it was settled
+ */
+pub async fn get_settled_transfer(
+    db: &ChainDB,
+    transfer_id: &str,
+) -> BasicResponse<TransferUnitsRegistry> {
+    let Some(settlement) = settlements(db).get::<TransferSettlement>(transfer_id).await else {
+        return error("Transfer not found".to_string());
+    };
+
+    match settlement.status {
+        SettlementStatus::Settled => BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(settlement.transfer),
+        },
+        SettlementStatus::Pending => unknown_outcome(transfer_id),
+        SettlementStatus::Failed => error("Transfer was not settled".to_string()),
+    }
+}
@@ -0,0 +1,214 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    features::{
+        contract::{contract_id, get_last_transaction, get_transactions, post_transaction},
+        structures::{BasicResponse, ContractTransactionData},
+    },
+    ChainDB,
+};
+
+/**
+ * Stored value of a key. A `None` value means the key was deleted
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KvEntry<V> {
+    pub key: String,
+    pub value: Option<V>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct KvIndex {
+    keys: Vec<String>,
+}
+
+/**
+ * Key-value store handle. Each key is stored in its own contract, so every change of a key
+ * is a contract transaction and its history can be fetched
+ */
+#[derive(Clone, Debug)]
+pub struct KeyValueStore {
+    namespace: String,
+    db: ChainDB,
+}
+
+impl KeyValueStore {
+    /**
+     * Returns an error if the namespace is empty or has a `:` or `/`, as those separate the
+     * namespace from the keys in the contract names
+     */
+    pub fn new(db: &ChainDB, namespace: &str) -> Result<Self, String> {
+        if namespace.is_empty() || namespace.contains([':', '/']) {
+            return Err(format!(
+                "Invalid key-value namespace {:?}: it can't be empty or have ':' or '/'",
+                namespace
+            ));
+        }
+
+        Ok(Self::internal(db, namespace))
+    }
+
+    // Store of a namespace used by the crate itself, always a valid one
+    pub(crate) fn internal(db: &ChainDB, namespace: &str) -> Self {
+        debug_assert!(!namespace.is_empty() && !namespace.contains([':', '/']));
+
+        Self {
+            namespace: namespace.to_string(),
+            db: db.clone(),
+        }
+    }
+
+    // The namespace has no '/', so entry and index names of different namespaces can't collide
+    fn key_contract_id(&self, key: &str) -> String {
+        contract_id(&self.db, &format!("kv:{}/entry/{}", self.namespace, key))
+    }
+
+    fn index_contract_id(&self) -> String {
+        contract_id(&self.db, &format!("kv:{}/index", self.namespace))
+    }
+
+    /**
+     * Get the current value of a key
+     */
+    pub async fn get<V: DeserializeOwned>(&self, key: &str) -> Option<V> {
+        get_last_transaction::<KvEntry<V>>(&self.db, &self.key_contract_id(key))
+            .await
+            .and_then(|tx| tx.data.value)
+    }
+
+    /**
+     * Set the value of a key
+     */
+    pub async fn set<V: Serialize>(&self, key: &str, value: &V) -> BasicResponse<String> {
+        let entry = KvEntry {
+            key: key.to_string(),
+            value: Some(value),
+        };
+        let response = post_transaction(&self.db, &self.key_contract_id(key), &entry).await;
+
+        if response.success {
+            if let Err(error_msg) = self.update_index(key, true).await {
+                return index_error(error_msg);
+            }
+        }
+
+        response
+    }
+
+    /**
+     * Delete a key. Its history is kept on chain
+     */
+    pub async fn delete(&self, key: &str) -> BasicResponse<String> {
+        let entry: KvEntry<()> = KvEntry {
+            key: key.to_string(),
+            value: None,
+        };
+        let response = post_transaction(&self.db, &self.key_contract_id(key), &entry).await;
+
+        if response.success {
+            if let Err(error_msg) = self.update_index(key, false).await {
+                return index_error(error_msg);
+            }
+        }
+
+        response
+    }
+
+    /**
+     * Set the value of a key only if its current value is equal to `expected`.
+     * Use `None` as `expected` to set the key only if it doesn't exist yet.
+     *
+     * The check is made on the client, so two concurrent writers may still both succeed.
+     */
+    pub async fn compare_and_set<V: Serialize + DeserializeOwned + PartialEq>(
+        &self,
+        key: &str,
+        expected: Option<&V>,
+        value: &V,
+    ) -> BasicResponse<String> {
+        let current: Option<V> = self.get(key).await;
+
+        if current.as_ref() != expected {
+            return BasicResponse {
+                success: false,
+                error_msg: "Current value does not match the expected value".to_string(),
+                data: None,
+            };
+        }
+
+        self.set(key, value).await
+    }
+
+    /**
+     * List the keys currently set in this namespace
+     */
+    pub async fn keys(&self) -> Vec<String> {
+        self.index().await.keys
+    }
+
+    async fn index(&self) -> KvIndex {
+        get_last_transaction::<KvIndex>(&self.db, &self.index_contract_id())
+            .await
+            .map(|tx| tx.data)
+            .unwrap_or_default()
+    }
+
+    /**
+     * Get the history of changes of a key. A list of transactions from the most recent to the
+     * most old in a range of depth. Deleted states are represented by `None`
+     */
+    pub async fn history<V: DeserializeOwned>(
+        &self,
+        key: &str,
+        depth: u64,
+    ) -> Vec<ContractTransactionData<Option<V>>> {
+        get_transactions::<KvEntry<V>>(&self.db, &self.key_contract_id(key), depth)
+            .await
+            .into_iter()
+            .map(|tx| ContractTransactionData {
+                tx_type: tx.tx_type,
+                contract_id: tx.contract_id,
+                timestamp: tx.timestamp,
+                data: tx.data.value,
+                block_hash: tx.block_hash,
+                block_height: tx.block_height,
+            })
+            .collect()
+    }
+
+    // Add or remove a key from the index. The lock serializes the index updates made by this
+    // process, writers in other processes may still overwrite each other
+    async fn update_index(&self, key: &str, present: bool) -> Result<(), String> {
+        let index_contract_id = self.index_contract_id();
+        let _guard = self.db.locks.lock(&index_contract_id).await;
+
+        let mut index = self.index().await;
+        if present == index.keys.iter().any(|k| k == key) {
+            return Ok(());
+        }
+
+        if present {
+            index.keys.push(key.to_string());
+        } else {
+            index.keys.retain(|k| k != key);
+        }
+
+        let response = post_transaction(&self.db, &index_contract_id, &index).await;
+        if !response.success {
+            return Err(response.error_msg);
+        }
+
+        Ok(())
+    }
+}
+
+fn index_error(error_msg: String) -> BasicResponse<String> {
+    BasicResponse {
+        success: false,
+        error_msg: format!(
+            "Key was written but its index could not be updated: {}",
+            error_msg
+        ),
+        data: None,
+    }
+}
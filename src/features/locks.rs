@@ -0,0 +1,36 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+// Unused locks are dropped once there are more than this amount
+const PRUNE_ABOVE: usize = 1024;
+
+/**
+ * Locks keyed by name (e.g. a table or a transfer id), shared by a `ChainDB` and all its clones.
+ * They serialize the read-modify-write changes made by this process. Other processes are not
+ * covered, the changes made by them are detected by the version checks of each feature
+ */
+#[derive(Debug, Clone, Default)]
+pub struct KeyedLocks {
+    locks: Arc<Mutex<HashMap<String, Arc<AsyncMutex<()>>>>>,
+}
+
+impl KeyedLocks {
+    /**
+     * Wait for the lock of a key. It's released when the guard is dropped
+     */
+    pub async fn lock(&self, key: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            if locks.len() > PRUNE_ABOVE {
+                locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            }
+            locks.entry(key.to_string()).or_default().clone()
+        };
+
+        lock.lock_owned().await
+    }
+}
@@ -0,0 +1,238 @@
+use std::{collections::VecDeque, marker::PhantomData, ops::Range, time::Duration};
+
+use futures::{stream, Stream};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    features::{
+        contract::{contract_id, get_last_transaction, get_transactions, post_transaction},
+        structures::{BasicResponse, ContractTransactionData},
+    },
+    ChainDB,
+};
+
+// Used by `stream_from` when there are no new items
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LogEntry<T> {
+    seq: u64,
+    item: T,
+}
+
+/**
+ * Item of a log with the information of the block where it was stored
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogItem<T> {
+    pub seq: u64,
+    pub item: T,
+    pub timestamp: Option<u64>,
+    pub block_hash: String,
+    pub block_height: u64,
+}
+
+impl<T> From<ContractTransactionData<LogEntry<T>>> for LogItem<T> {
+    fn from(tx: ContractTransactionData<LogEntry<T>>) -> Self {
+        Self {
+            seq: tx.data.seq,
+            item: tx.data.item,
+            timestamp: tx.timestamp,
+            block_hash: tx.block_hash,
+            block_height: tx.block_height,
+        }
+    }
+}
+
+/**
+ * Append-only log. Every appended item is a new contract transaction and gets a sequence
+ * number, starting from 0. Useful for chat messages and event feeds.
+ *
+ * Sequence numbers are given by the client. Appends made through the same `ChainDB` (or its
+ * clones) are serialized, but writers in other processes are not: two processes appending at
+ * the same time may get the same sequence number, so a log should be written by one process.
+ */
+#[derive(Debug)]
+pub struct AppendLog<T> {
+    contract_id: String,
+    db: ChainDB,
+    poll_interval: Duration,
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for AppendLog<T> {
+    fn clone(&self) -> Self {
+        Self {
+            contract_id: self.contract_id.clone(),
+            db: self.db.clone(),
+            poll_interval: self.poll_interval,
+            _item: PhantomData,
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> AppendLog<T> {
+    pub fn new(db: &ChainDB, log_name: &str) -> Self {
+        Self::with_contract_name(db, &format!("log:{}", log_name))
+    }
+
+    // Log stored in the given contract, used by the features built on logs (e.g. aggregates)
+    pub(crate) fn with_contract_name(db: &ChainDB, contract_name: &str) -> Self {
+        Self {
+            contract_id: contract_id(db, contract_name),
+            db: db.clone(),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            _item: PhantomData,
+        }
+    }
+
+    /**
+     * Set how often `stream_from` checks for new items
+     */
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /**
+     * Number of items in the log
+     */
+    pub async fn len(&self) -> u64 {
+        get_last_transaction::<LogEntry<T>>(&self.db, &self.contract_id)
+            .await
+            .map(|tx| tx.data.seq + 1)
+            .unwrap_or(0)
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    /**
+     * Append a new item to the log. Returns the sequence number of the item
+     */
+    pub async fn append(&self, item: &T) -> BasicResponse<u64> {
+        // Held until the item is stored, so appenders of this process get different numbers
+        let _guard = self.db.locks.lock(&self.contract_id).await;
+
+        let seq = self.len().await;
+        self.post(seq, item).await
+    }
+
+    /**
+     * Append an item only if the log still has `len` items. Returns `None` if it doesn't
+     */
+    pub(crate) async fn append_at(&self, len: u64, item: &T) -> Option<BasicResponse<u64>> {
+        let _guard = self.db.locks.lock(&self.contract_id).await;
+
+        if self.len().await != len {
+            return None;
+        }
+        Some(self.post(len, item).await)
+    }
+
+    async fn post(&self, seq: u64, item: &T) -> BasicResponse<u64> {
+        let response = post_transaction(&self.db, &self.contract_id, &LogEntry { seq, item }).await;
+
+        BasicResponse {
+            success: response.success,
+            error_msg: response.error_msg,
+            data: if response.success { Some(seq) } else { None },
+        }
+    }
+
+    /**
+     * Read the items within the given range of sequence numbers, from the most old to the most recent
+     */
+    pub async fn read(&self, range: Range<u64>) -> Vec<LogItem<T>> {
+        let len = self.len().await;
+        let end = range.end.min(len);
+
+        if range.start >= end {
+            return vec![];
+        }
+
+        self.fetch(len - range.start)
+            .await
+            .into_iter()
+            .filter(|item| range.start <= item.seq && item.seq < end)
+            .collect()
+    }
+
+    /**
+     * Read the last `n` items, from the most old to the most recent
+     */
+    pub async fn tail(&self, n: u64) -> Vec<LogItem<T>> {
+        if n == 0 {
+            return vec![];
+        }
+
+        self.fetch(n).await
+    }
+
+    /**
+     * Stream the items stored from the given block height on, from the most old to the most
+     * recent. The stream never ends: once all stored items were returned, it waits for new ones.
+     */
+    pub fn stream_from(&self, block_height: u64) -> impl Stream<Item = LogItem<T>> {
+        let state = (self.clone(), VecDeque::<LogItem<T>>::new(), None::<u64>);
+
+        stream::unfold(state, move |(log, mut buffer, mut next_seq)| async move {
+            loop {
+                if let Some(item) = buffer.pop_front() {
+                    next_seq = Some(item.seq + 1);
+                    return Some((item, (log, buffer, next_seq)));
+                }
+
+                match next_seq {
+                    None => {
+                        let items = log.items_from_height(block_height).await;
+                        if items.is_empty() {
+                            next_seq = Some(log.len().await);
+                        }
+                        buffer.extend(items);
+                    }
+                    Some(seq) => {
+                        let items = log.read(seq..u64::MAX).await;
+                        if items.is_empty() {
+                            tokio::time::sleep(log.poll_interval).await;
+                        }
+                        buffer.extend(items);
+                    }
+                }
+            }
+        })
+    }
+
+    // Fetch the last `depth` items in chronological order
+    async fn fetch(&self, depth: u64) -> Vec<LogItem<T>> {
+        let mut items: Vec<LogItem<T>> =
+            get_transactions::<LogEntry<T>>(&self.db, &self.contract_id, depth)
+                .await
+                .into_iter()
+                .map(LogItem::from)
+                .collect();
+        items.reverse();
+        items
+    }
+
+    async fn items_from_height(&self, block_height: u64) -> Vec<LogItem<T>> {
+        let mut depth = 16;
+
+        loop {
+            let items = self.fetch(depth).await;
+            let reached_height = items
+                .first()
+                .is_none_or(|item| item.block_height < block_height);
+
+            if reached_height || (items.len() as u64) < depth {
+                return items
+                    .into_iter()
+                    .filter(|item| item.block_height >= block_height)
+                    .collect();
+            }
+
+            depth *= 2;
+        }
+    }
+}
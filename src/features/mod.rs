@@ -1,3 +1,30 @@
+pub mod aggregate;
+pub mod assets;
+pub mod batch;
 pub mod constants;
+pub mod contract;
+pub mod credentials;
+pub mod escrow;
+pub mod idempotency;
+pub mod kv;
+pub mod locks;
+pub mod log;
+pub mod multisig;
+pub mod policy;
+pub mod profile;
+pub mod reconciliation;
+pub mod recovery;
+pub mod scheduler;
+pub mod session;
+pub mod statement;
 pub mod structures;
 pub mod table;
+pub mod throttle;
+pub mod token;
+pub mod transaction;
+pub mod transfer_operation;
+pub mod transfer_query;
+pub mod transfer_records;
+pub mod two_factor;
+pub mod units;
+pub mod validation;
@@ -0,0 +1,500 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::OwnedMutexGuard;
+
+use crate::{
+    features::{structures::BasicResponse, table::Table, units::Units},
+    ChainDB,
+};
+
+// Prefix of the tables holding the approvers and proposals of each multi-sig account
+const MULTISIG_PREFIX: &str = "__multisig";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ProposalStatus {
+    Pending,
+    // Enough approvals, but the transfer failed. It can be executed again
+    Approved,
+    Executed,
+    // Not enough approvers left to reach the threshold
+    Rejected,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ProposalAction {
+    Proposed,
+    Approved,
+    Rejected,
+    Executed,
+    ExecutionFailed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProposalEvent {
+    pub user_id: String,
+    pub action: ProposalAction,
+    pub timestamp: u64,
+    pub error_msg: Option<String>,
+}
+
+/**
+ * Transfer from a multi-sig account waiting for the approvals
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferProposal {
+    pub id: String,
+    pub to: String,
+    pub units: Units,
+    pub memo: Option<String>,
+    pub status: ProposalStatus,
+    pub approvals: Vec<String>,
+    pub rejections: Vec<String>,
+    // Every action made on the proposal, from the most old to the most recent
+    pub history: Vec<ProposalEvent>,
+}
+
+/**
+ * Approvers and threshold set by the owner of the account
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigurationChange {
+    pub approvers: Vec<String>,
+    pub threshold: usize,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MultiSigAccount {
+    pub approvers: Vec<String>,
+    pub threshold: usize,
+    pub proposals: Vec<TransferProposal>,
+    // Every configuration of the account, from the most old to the most recent
+    #[serde(default)]
+    pub configurations: Vec<ConfigurationChange>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn error<D>(error_msg: String) -> BasicResponse<D> {
+    BasicResponse {
+        success: false,
+        error_msg,
+        data: None,
+    }
+}
+
+fn push_event(
+    proposal: &mut TransferProposal,
+    user_id: &str,
+    action: ProposalAction,
+    error_msg: Option<String>,
+) {
+    proposal.history.push(ProposalEvent {
+        user_id: user_id.to_string(),
+        action,
+        timestamp: now(),
+        error_msg,
+    });
+}
+
+/**
+ * Multi-signature handle of an account. Transfers from the account are proposed and only made
+ * after M of its N approvers approved them. Approvers sign in with their own credentials.
+ *
+ * Approvers and proposals are kept in a table, so its history has every change made to them.
+ * Note that `transfer_units` can still move units of the account directly, the multi-sig
+ * workflow only applies to the transfers made through this handle.
+ */
+#[derive(Clone, Debug)]
+pub struct MultiSig {
+    account: String,
+    db: ChainDB,
+}
+
+impl MultiSig {
+    pub fn new(db: &ChainDB, account: &str) -> Self {
+        Self {
+            account: account.to_string(),
+            db: db.clone(),
+        }
+    }
+
+    fn table_name(&self) -> String {
+        format!("{}:{}", MULTISIG_PREFIX, self.account)
+    }
+
+    // Serializes the changes of the account table made by every handle of the same `ChainDB`.
+    // Changes made by other processes in between make the write fail (see `persist`)
+    async fn lock(&self) -> OwnedMutexGuard<()> {
+        self.db.locks.lock(&self.table_name()).await
+    }
+
+    async fn table(&self) -> Table<MultiSigAccount> {
+        Table::get(&self.db, &self.table_name(), MultiSigAccount::default).await
+    }
+
+    async fn persist(&self, table: &Table<MultiSigAccount>) -> BasicResponse<String> {
+        table.persist_checked().await
+    }
+
+    // Sign in an approver of the account, returning its user id
+    async fn sign_in(
+        &self,
+        account: &MultiSigAccount,
+        user_name: &str,
+        password: &str,
+    ) -> Result<String, String> {
+        let user = self.db.get_user_account(user_name, password).await;
+        let Some(user) = user.data else {
+            return Err(user.error_msg);
+        };
+
+        if !account.approvers.contains(&user.id) {
+            return Err("User is not an approver of this account".to_string());
+        }
+
+        Ok(user.id)
+    }
+
+    /**
+     * Set the approvers of the account and the amount of approvals (threshold) a transfer
+     * needs. Only the owner of the account can do it, signing in with its credentials.
+     *
+     * It can be done again to rotate the approvers. The votes of the removed approvers are
+     * dropped from the proposals not executed yet, and their status is evaluated again with the
+     * new threshold: a proposal that reaches it is approved and has to be made with `execute`.
+     * Every configuration is kept in `configurations`
+     */
+    pub async fn configure(
+        &self,
+        user_name: &str,
+        password: &str,
+        approvers: &[&str],
+        threshold: usize,
+    ) -> BasicResponse<MultiSigAccount> {
+        let owner = self.db.get_user_account(user_name, password).await;
+        let Some(owner) = owner.data else {
+            return error(owner.error_msg);
+        };
+        if owner.id != self.account {
+            return error("Only the owner of the account can configure it".to_string());
+        }
+
+        let mut approvers: Vec<String> = approvers.iter().map(|id| id.to_string()).collect();
+        approvers.sort();
+        approvers.dedup();
+
+        if threshold == 0 || threshold > approvers.len() {
+            return error("Threshold must be between 1 and the amount of approvers".to_string());
+        }
+
+        for approver in approvers.iter() {
+            let account = self.db.get_user_account_by_id(approver).await;
+            if !account.success {
+                return error(account.error_msg);
+            }
+        }
+
+        let _guard = self.lock().await;
+        let mut table = self.table().await;
+
+        for proposal in table.table.proposals.iter_mut() {
+            if !matches!(
+                proposal.status,
+                ProposalStatus::Pending | ProposalStatus::Approved
+            ) {
+                continue;
+            }
+
+            proposal.approvals.retain(|id| approvers.contains(id));
+            proposal.rejections.retain(|id| approvers.contains(id));
+            proposal.status = if proposal.approvals.len() >= threshold {
+                ProposalStatus::Approved
+            } else if approvers.len() - proposal.rejections.len() < threshold {
+                ProposalStatus::Rejected
+            } else {
+                ProposalStatus::Pending
+            };
+        }
+
+        table.table.configurations.push(ConfigurationChange {
+            approvers: approvers.clone(),
+            threshold,
+            timestamp: now(),
+        });
+        table.table.approvers = approvers;
+        table.table.threshold = threshold;
+
+        let persisted = self.persist(&table).await;
+        if !persisted.success {
+            return error(persisted.error_msg);
+        }
+
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(table.table),
+        }
+    }
+
+    /**
+     * Get the approvers, threshold and proposals of the account
+     */
+    pub async fn get_account(&self) -> MultiSigAccount {
+        self.table().await.table
+    }
+
+    pub async fn get_proposal(&self, proposal_id: &str) -> BasicResponse<TransferProposal> {
+        let account = self.get_account().await;
+
+        match account.proposals.into_iter().find(|p| p.id == proposal_id) {
+            Some(proposal) => BasicResponse {
+                success: true,
+                error_msg: String::new(),
+                data: Some(proposal),
+            },
+            None => error("Proposal not found".to_string()),
+        }
+    }
+
+    /**
+     * List the proposals waiting for approvals
+     */
+    pub async fn pending_proposals(&self) -> Vec<TransferProposal> {
+        self.get_account()
+            .await
+            .proposals
+            .into_iter()
+            .filter(|proposal| proposal.status == ProposalStatus::Pending)
+            .collect()
+    }
+
+    /**
+     * Propose a transfer from the account. The proposal counts as an approval of the proposer
+     */
+    pub async fn propose(
+        &self,
+        user_name: &str,
+        password: &str,
+        to: &str,
+        units: Units,
+        memo: Option<String>,
+    ) -> BasicResponse<TransferProposal> {
+        let charset = "abcdefghijklmnopqrstuvwxyz0123456789";
+        let mut proposal = TransferProposal {
+            id: random_string::generate(16, charset),
+            to: to.to_string(),
+            units,
+            memo,
+            status: ProposalStatus::Pending,
+            approvals: vec![],
+            rejections: vec![],
+            history: vec![],
+        };
+
+        {
+            let _guard = self.lock().await;
+            let mut table = self.table().await;
+            let user_id = match self.sign_in(&table.table, user_name, password).await {
+                Ok(user_id) => user_id,
+                Err(error_msg) => return error(error_msg),
+            };
+
+            push_event(&mut proposal, &user_id, ProposalAction::Proposed, None);
+            table.table.proposals.push(proposal.clone());
+
+            let persisted = self.persist(&table).await;
+            if !persisted.success {
+                return error(persisted.error_msg);
+            }
+        }
+
+        self.approve(&proposal.id, user_name, password).await
+    }
+
+    /**
+     * Approve a proposal. The transfer is made once the threshold is reached
+     */
+    pub async fn approve(
+        &self,
+        proposal_id: &str,
+        user_name: &str,
+        password: &str,
+    ) -> BasicResponse<TransferProposal> {
+        self.vote(proposal_id, user_name, password, true).await
+    }
+
+    /**
+     * Reject a proposal. It's rejected once the threshold can't be reached anymore
+     */
+    pub async fn reject(
+        &self,
+        proposal_id: &str,
+        user_name: &str,
+        password: &str,
+    ) -> BasicResponse<TransferProposal> {
+        self.vote(proposal_id, user_name, password, false).await
+    }
+
+    /**
+     * Make the transfer of an approved proposal whose transfer failed, e.g. when the account
+     * didn't have enough units
+     */
+    pub async fn execute(
+        &self,
+        proposal_id: &str,
+        user_name: &str,
+        password: &str,
+    ) -> BasicResponse<TransferProposal> {
+        let _guard = self.lock().await;
+        let mut table = self.table().await;
+        let user_id = match self.sign_in(&table.table, user_name, password).await {
+            Ok(user_id) => user_id,
+            Err(error_msg) => return error(error_msg),
+        };
+
+        let Some(index) = table
+            .table
+            .proposals
+            .iter()
+            .position(|p| p.id == proposal_id)
+        else {
+            return error("Proposal not found".to_string());
+        };
+        if table.table.proposals[index].status != ProposalStatus::Approved {
+            return error("Proposal is not approved".to_string());
+        }
+
+        self.execute_proposal(&mut table, index, &user_id).await
+    }
+
+    async fn vote(
+        &self,
+        proposal_id: &str,
+        user_name: &str,
+        password: &str,
+        approve: bool,
+    ) -> BasicResponse<TransferProposal> {
+        let _guard = self.lock().await;
+        let mut table = self.table().await;
+        let user_id = match self.sign_in(&table.table, user_name, password).await {
+            Ok(user_id) => user_id,
+            Err(error_msg) => return error(error_msg),
+        };
+
+        let threshold = table.table.threshold;
+        let approvers = table.table.approvers.len();
+        let Some(index) = table
+            .table
+            .proposals
+            .iter()
+            .position(|p| p.id == proposal_id)
+        else {
+            return error("Proposal not found".to_string());
+        };
+
+        let proposal = &mut table.table.proposals[index];
+        if proposal.status != ProposalStatus::Pending {
+            return error(format!("Proposal is not pending ({:?})", proposal.status));
+        }
+        if proposal.approvals.contains(&user_id) || proposal.rejections.contains(&user_id) {
+            return error("User already voted on this proposal".to_string());
+        }
+
+        if approve {
+            proposal.approvals.push(user_id.clone());
+            push_event(proposal, &user_id, ProposalAction::Approved, None);
+        } else {
+            proposal.rejections.push(user_id.clone());
+            push_event(proposal, &user_id, ProposalAction::Rejected, None);
+        }
+
+        if proposal.approvals.len() >= threshold {
+            proposal.status = ProposalStatus::Approved;
+        } else if approvers - proposal.rejections.len() < threshold {
+            proposal.status = ProposalStatus::Rejected;
+        }
+
+        // Record the vote before moving any unit
+        let persisted = self.persist(&table).await;
+        if !persisted.success {
+            return error(persisted.error_msg);
+        }
+
+        if table.table.proposals[index].status == ProposalStatus::Approved {
+            return self.execute_proposal(&mut table, index, &user_id).await;
+        }
+
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(table.table.proposals[index].clone()),
+        }
+    }
+
+    async fn execute_proposal(
+        &self,
+        table: &mut Table<MultiSigAccount>,
+        index: usize,
+        user_id: &str,
+    ) -> BasicResponse<TransferProposal> {
+        let proposal = &mut table.table.proposals[index];
+
+        let transfer = self
+            .db
+            .transfer_units_with_id(
+                &format!("multisig:{}:{}", self.account, proposal.id),
+                &self.account,
+                &proposal.to,
+                proposal.units,
+                proposal.memo.clone(),
+            )
+            .await;
+
+        if transfer.success {
+            proposal.status = ProposalStatus::Executed;
+            push_event(proposal, user_id, ProposalAction::Executed, None);
+        } else {
+            push_event(
+                proposal,
+                user_id,
+                ProposalAction::ExecutionFailed,
+                Some(transfer.error_msg.clone()),
+            );
+        }
+
+        let proposal = proposal.clone();
+        // With the transfer id, executing it again never makes the transfer twice
+        let persisted = self.persist(table).await;
+        if !persisted.success {
+            return BasicResponse {
+                success: false,
+                error_msg: format!(
+                    "Proposal {} could not be recorded: {}",
+                    proposal.id, persisted.error_msg
+                ),
+                data: Some(proposal),
+            };
+        }
+
+        if !transfer.success {
+            return BasicResponse {
+                success: false,
+                error_msg: transfer.error_msg,
+                data: Some(proposal),
+            };
+        }
+
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(proposal),
+        }
+    }
+}
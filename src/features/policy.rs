@@ -0,0 +1,326 @@
+use std::{
+    collections::HashSet,
+    fmt::Debug,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    features::{structures::TransferUnitsRegistry, transfer_records, units::Units},
+    ChainDB,
+};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/**
+ * Key of the lock held while a transfer of the sender is evaluated and sent
+ */
+pub(crate) fn sender_lock_key(from: &str) -> String {
+    format!("transfers_from:{}", from)
+}
+
+/**
+ * Transfer being evaluated by the policies. `history` has the transfer records kept by the
+ * client for the sender (see `transfer_records`), fetched only if a policy needs them
+ */
+#[derive(Debug)]
+pub struct TransferContext {
+    pub from: String,
+    pub to: String,
+    pub units: Units,
+    pub memo: Option<String>,
+    // Unix timestamp (seconds)
+    pub now: u64,
+    pub history: Vec<TransferUnitsRegistry>,
+}
+
+impl TransferContext {
+    /**
+     * Transfers sent by the sender in the last `window`. Only records whose time is known are
+     * counted, records without timestamp can't be placed in the window
+     */
+    pub fn sent_within(&self, window: Duration) -> impl Iterator<Item = &TransferUnitsRegistry> {
+        let since = self.now.saturating_sub(window.as_secs());
+
+        self.history.iter().filter(move |transfer| {
+            transfer.from == self.from && transfer.timestamp.is_some_and(|t| t >= since)
+        })
+    }
+}
+
+/**
+ * Rule checked before each transfer. Returning an error rejects the transfer, the message
+ * describes why
+ */
+pub trait TransferPolicy: Debug + Send + Sync {
+    fn name(&self) -> String;
+
+    fn evaluate(&self, context: &TransferContext) -> Result<(), String>;
+
+    // Whether the policy needs the transfer records of the sender
+    fn needs_history(&self) -> bool {
+        false
+    }
+
+    /**
+     * Apply the policy only to the transfers sent by the given user
+     */
+    fn only_for(self, user_id: &str) -> OnlyFor<Self>
+    where
+        Self: Sized,
+    {
+        OnlyFor {
+            user_id: user_id.to_string(),
+            policy: self,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct OnlyFor<P> {
+    user_id: String,
+    policy: P,
+}
+
+impl<P: TransferPolicy> TransferPolicy for OnlyFor<P> {
+    fn name(&self) -> String {
+        format!("{} ({})", self.policy.name(), self.user_id)
+    }
+
+    fn evaluate(&self, context: &TransferContext) -> Result<(), String> {
+        if context.from != self.user_id {
+            return Ok(());
+        }
+
+        self.policy.evaluate(context)
+    }
+
+    fn needs_history(&self) -> bool {
+        self.policy.needs_history()
+    }
+}
+
+/**
+ * Maximum units of a single transfer
+ */
+#[derive(Debug)]
+pub struct MaxTransferAmount {
+    max: Units,
+}
+
+impl MaxTransferAmount {
+    pub fn new(max: Units) -> Self {
+        Self { max }
+    }
+}
+
+impl TransferPolicy for MaxTransferAmount {
+    fn name(&self) -> String {
+        "MaxTransferAmount".to_string()
+    }
+
+    fn evaluate(&self, context: &TransferContext) -> Result<(), String> {
+        if context.units > self.max {
+            return Err(format!(
+                "{} units is above the maximum of {} units per transfer",
+                context.units, self.max
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * Maximum units sent by an account in the last 24 hours
+ */
+#[derive(Debug)]
+pub struct DailyLimit {
+    limit: Units,
+}
+
+impl DailyLimit {
+    pub fn new(limit: Units) -> Self {
+        Self { limit }
+    }
+}
+
+impl TransferPolicy for DailyLimit {
+    fn name(&self) -> String {
+        "DailyLimit".to_string()
+    }
+
+    fn evaluate(&self, context: &TransferContext) -> Result<(), String> {
+        let sent: u128 = context
+            .sent_within(DAY)
+            .map(|transfer| transfer.units.amount() as u128)
+            .sum();
+
+        if sent + context.units.amount() as u128 > self.limit.amount() as u128 {
+            return Err(format!(
+                "{} units were already sent in the last 24 hours, sending {} more exceeds the daily limit of {} units",
+                sent, context.units, self.limit
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn needs_history(&self) -> bool {
+        true
+    }
+}
+
+/**
+ * Only transfers to the listed users are allowed
+ */
+#[derive(Debug)]
+pub struct AllowList {
+    user_ids: HashSet<String>,
+}
+
+impl AllowList {
+    pub fn new(user_ids: &[&str]) -> Self {
+        Self {
+            user_ids: user_ids.iter().map(|id| id.to_string()).collect(),
+        }
+    }
+}
+
+impl TransferPolicy for AllowList {
+    fn name(&self) -> String {
+        "AllowList".to_string()
+    }
+
+    fn evaluate(&self, context: &TransferContext) -> Result<(), String> {
+        if !self.user_ids.contains(&context.to) {
+            return Err(format!("Receiver {} is not in the allow list", context.to));
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * Transfers to the listed users are not allowed
+ */
+#[derive(Debug)]
+pub struct DenyList {
+    user_ids: HashSet<String>,
+}
+
+impl DenyList {
+    pub fn new(user_ids: &[&str]) -> Self {
+        Self {
+            user_ids: user_ids.iter().map(|id| id.to_string()).collect(),
+        }
+    }
+}
+
+impl TransferPolicy for DenyList {
+    fn name(&self) -> String {
+        "DenyList".to_string()
+    }
+
+    fn evaluate(&self, context: &TransferContext) -> Result<(), String> {
+        if self.user_ids.contains(&context.to) {
+            return Err(format!("Receiver {} is in the deny list", context.to));
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * Maximum amount of transfers sent by an account in a time window
+ */
+#[derive(Debug)]
+pub struct Velocity {
+    max_transfers: usize,
+    window: Duration,
+}
+
+impl Velocity {
+    pub fn new(max_transfers: usize, window: Duration) -> Self {
+        Self {
+            max_transfers,
+            window,
+        }
+    }
+}
+
+impl TransferPolicy for Velocity {
+    fn name(&self) -> String {
+        "Velocity".to_string()
+    }
+
+    fn evaluate(&self, context: &TransferContext) -> Result<(), String> {
+        let sent = context.sent_within(self.window).count();
+
+        if sent >= self.max_transfers {
+            return Err(format!(
+                "{} transfers were already sent in the last {} seconds, the maximum is {}",
+                sent,
+                self.window.as_secs(),
+                self.max_transfers
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn needs_history(&self) -> bool {
+        true
+    }
+}
+
+/**
+ * Evaluate the transfer policies of the database in order. The first rejection is returned.
+ * Policies using the history only see the transfers made through this library, the ones kept
+ * by the client with their time.
+ *
+ * Callers hold the lock of the sender (see `sender_lock_key`) until the transfer is sent, so
+ * the transfers of the same sender are evaluated against an up to date history
+ */
+pub async fn evaluate(
+    db: &ChainDB,
+    from: &str,
+    to: &str,
+    units: Units,
+    memo: &Option<String>,
+) -> Result<(), String> {
+    if db.transfer_policies.is_empty() {
+        return Ok(());
+    }
+
+    let needs_history = db.transfer_policies.iter().any(|p| p.needs_history());
+    // The server sends no timestamps, the records kept by the client have them
+    let history = if needs_history {
+        transfer_records::get_records(db, from).await
+    } else {
+        vec![]
+    };
+
+    let context = TransferContext {
+        from: from.to_string(),
+        to: to.to_string(),
+        units,
+        memo: memo.clone(),
+        now: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        history,
+    };
+
+    for policy in db.transfer_policies.iter() {
+        if let Err(reason) = policy.evaluate(&context) {
+            return Err(format!(
+                "Transfer rejected by {}: {}",
+                policy.name(),
+                reason
+            ));
+        }
+    }
+
+    Ok(())
+}
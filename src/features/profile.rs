@@ -0,0 +1,76 @@
+use std::collections::BTreeMap;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    features::{
+        contract::{contract_id, get_last_transaction, post_transaction},
+        session::{register_user_table, user_table_name},
+        structures::{BasicResponse, ContractTransactionData, TransactionType},
+    },
+    ChainDB,
+};
+
+// Name of the profile table owned by each user
+pub(crate) const PROFILE_TABLE: &str = "__profile";
+
+/**
+ * Profile with the fields most apps need. Any other serializable type can be used as a profile
+ */
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub settings: BTreeMap<String, String>,
+}
+
+fn error<D>(error_msg: String) -> BasicResponse<D> {
+    BasicResponse {
+        success: false,
+        error_msg,
+        data: None,
+    }
+}
+
+fn profile_contract_id(db: &ChainDB, user_id: &str) -> String {
+    contract_id(db, &user_table_name(user_id, PROFILE_TABLE))
+}
+
+/**
+ * Read the profile of a user by its account id
+ */
+pub async fn get_profile<Profile: DeserializeOwned>(
+    db: &ChainDB,
+    user_id: &str,
+) -> BasicResponse<Profile> {
+    let contract_data: Option<ContractTransactionData<Profile>> =
+        get_last_transaction(db, &profile_contract_id(db, user_id)).await;
+
+    match contract_data {
+        Some(data) if data.tx_type == TransactionType::CONTRACT => BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(data.data),
+        },
+        _ => error("Profile not found".to_string()),
+    }
+}
+
+/**
+ * Store the profile of a user. Only called by the session of the user (see
+ * `UserSession::set_profile`). That's only the shape of the API, not a check: the profile is a
+ * plain table, so anyone with the database access key can write it
+ */
+pub(crate) async fn set_profile<Profile: Serialize>(
+    db: &ChainDB,
+    user_id: &str,
+    profile: &Profile,
+) -> BasicResponse<String> {
+    // Registered as a user table, so it moves along with the account on password changes
+    let registered = register_user_table(db, user_id, PROFILE_TABLE).await;
+    if !registered.success {
+        return registered;
+    }
+
+    post_transaction(db, &profile_contract_id(db, user_id), profile).await
+}
@@ -0,0 +1,263 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    features::{
+        structures::{BasicResponse, TransferUnitsRegistry},
+        units::Units,
+    },
+    ChainDB,
+};
+
+/**
+ * Account to be audited. `initial_units` is the amount of units granted on the account
+ * creation, if known
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditAccount {
+    pub user_id: String,
+    pub initial_units: Option<Units>,
+}
+
+impl AuditAccount {
+    pub fn new(user_id: &str, initial_units: Option<Units>) -> Self {
+        Self {
+            user_id: user_id.to_string(),
+            initial_units,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Discrepancy {
+    AccountNotFound {
+        user_id: String,
+        error_msg: String,
+    },
+    // The transfer records of the account could not be fetched, so it was not audited
+    HistoryNotFetched {
+        user_id: String,
+        error_msg: String,
+    },
+    // Initial units plus the transfers are different from the current balance
+    BalanceMismatch {
+        user_id: String,
+        expected: i128,
+        actual: Units,
+    },
+    // The account sent more units than it received plus its current balance
+    NegativeInitialUnits {
+        user_id: String,
+        initial_units: i128,
+    },
+}
+
+/**
+ * Transfer found in the history of an account without its counterpart
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrphanTransfer {
+    pub user_id: String,
+    pub transfer: TransferUnitsRegistry,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountReconciliation {
+    pub user_id: String,
+    pub balance: Units,
+    pub total_in: Units,
+    pub total_out: Units,
+    // Balance minus the received units plus the sent units
+    pub inferred_initial_units: i128,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReconciliationReport {
+    pub accounts: Vec<AccountReconciliation>,
+    pub discrepancies: Vec<Discrepancy>,
+    pub orphan_transfers: Vec<OrphanTransfer>,
+    // Sum of the balances of the audited accounts
+    pub total_supply: Units,
+    // Sum of the initial units, if known for all the audited accounts
+    pub total_initial_units: Option<Units>,
+}
+
+impl ReconciliationReport {
+    pub fn is_consistent(&self) -> bool {
+        self.discrepancies.is_empty() && self.orphan_transfers.is_empty()
+    }
+}
+
+type TransferKey = (String, String, Units);
+
+fn transfer_key(transfer: &TransferUnitsRegistry) -> TransferKey {
+    (transfer.from.clone(), transfer.to.clone(), transfer.units)
+}
+
+/**
+ * Replay the transfer records of the accounts and compare them against the current balances
+ */
+pub async fn reconcile(
+    db: &ChainDB,
+    accounts: &[AuditAccount],
+) -> BasicResponse<ReconciliationReport> {
+    let mut report = ReconciliationReport {
+        accounts: vec![],
+        discrepancies: vec![],
+        orphan_transfers: vec![],
+        total_supply: Units::ZERO,
+        total_initial_units: Some(Units::ZERO),
+    };
+
+    let mut histories: BTreeMap<String, Vec<TransferUnitsRegistry>> = BTreeMap::new();
+    // Existence of the counterparties outside the audited accounts
+    let mut known_accounts: HashMap<String, bool> = HashMap::new();
+
+    for audit in accounts {
+        let user_id = audit.user_id.as_str();
+        let account = db.get_user_account_by_id(user_id).await;
+        let Some(account) = account.data else {
+            report.discrepancies.push(Discrepancy::AccountNotFound {
+                user_id: user_id.to_string(),
+                error_msg: account.error_msg,
+            });
+            report.total_initial_units = None;
+            continue;
+        };
+        known_accounts.insert(user_id.to_string(), true);
+
+        let transfers = db.get_all_transfers_by_user_id(user_id).await;
+        let Some(transfers) = transfers.data else {
+            report.discrepancies.push(Discrepancy::HistoryNotFetched {
+                user_id: user_id.to_string(),
+                error_msg: transfers.error_msg,
+            });
+            report.total_initial_units = None;
+            continue;
+        };
+
+        let mut total_in: i128 = 0;
+        let mut total_out: i128 = 0;
+        for transfer in transfers.iter() {
+            if transfer.to == user_id {
+                total_in += transfer.units.amount() as i128;
+            }
+            if transfer.from == user_id {
+                total_out += transfer.units.amount() as i128;
+            }
+        }
+
+        let balance = account.units.amount() as i128;
+        let inferred_initial_units = balance - total_in + total_out;
+
+        match audit.initial_units {
+            Some(initial_units) => {
+                let expected = initial_units.amount() as i128 + total_in - total_out;
+                if expected != balance {
+                    report.discrepancies.push(Discrepancy::BalanceMismatch {
+                        user_id: user_id.to_string(),
+                        expected,
+                        actual: account.units,
+                    });
+                }
+                report.total_initial_units = report
+                    .total_initial_units
+                    .and_then(|total| total.checked_add(initial_units));
+            }
+            None => {
+                report.total_initial_units = None;
+                if inferred_initial_units < 0 {
+                    report
+                        .discrepancies
+                        .push(Discrepancy::NegativeInitialUnits {
+                            user_id: user_id.to_string(),
+                            initial_units: inferred_initial_units,
+                        });
+                }
+            }
+        }
+
+        let Some(total_supply) = report.total_supply.checked_add(account.units) else {
+            return BasicResponse {
+                success: false,
+                error_msg: "Total supply of the audited accounts is too large".to_string(),
+                data: None,
+            };
+        };
+        report.total_supply = total_supply;
+        report.accounts.push(AccountReconciliation {
+            user_id: user_id.to_string(),
+            balance: account.units,
+            total_in: Units::new(total_in.min(u64::MAX as i128) as u64),
+            total_out: Units::new(total_out.min(u64::MAX as i128) as u64),
+            inferred_initial_units,
+        });
+        histories.insert(user_id.to_string(), transfers);
+    }
+
+    for (user_id, transfers) in histories.iter() {
+        // Amount of each transfer (from, to, units) with every counterparty
+        let mut counts: BTreeMap<TransferKey, Vec<&TransferUnitsRegistry>> = BTreeMap::new();
+
+        for transfer in transfers {
+            if &transfer.from != user_id && &transfer.to != user_id {
+                report.orphan_transfers.push(OrphanTransfer {
+                    user_id: user_id.clone(),
+                    transfer: transfer.clone(),
+                    reason: "Transfer does not involve the account".to_string(),
+                });
+                continue;
+            }
+            counts
+                .entry(transfer_key(transfer))
+                .or_default()
+                .push(transfer);
+        }
+
+        for ((from, to, units), own) in counts {
+            let counterparty = if &from == user_id { &to } else { &from };
+
+            match histories.get(counterparty) {
+                Some(counterparty_transfers) => {
+                    let theirs = counterparty_transfers
+                        .iter()
+                        .filter(|t| t.from == from && t.to == to && t.units == units)
+                        .count();
+
+                    // Extra records on the counterparty side are reported by its own iteration
+                    for transfer in own.iter().skip(theirs) {
+                        report.orphan_transfers.push(OrphanTransfer {
+                            user_id: user_id.clone(),
+                            transfer: (*transfer).clone(),
+                            reason: "Transfer is missing in the counterparty records".to_string(),
+                        });
+                    }
+                }
+                None => {
+                    if !known_accounts.contains_key(counterparty) {
+                        let exists = db.get_user_account_by_id(counterparty).await.success;
+                        known_accounts.insert(counterparty.clone(), exists);
+                    }
+
+                    if known_accounts.get(counterparty) == Some(&false) {
+                        for transfer in own {
+                            report.orphan_transfers.push(OrphanTransfer {
+                                user_id: user_id.clone(),
+                                transfer: transfer.clone(),
+                                reason: "Counterparty account not found".to_string(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    BasicResponse {
+        success: true,
+        error_msg: String::new(),
+        data: Some(report),
+    }
+}
@@ -0,0 +1,217 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    features::{
+        credentials,
+        kv::KeyValueStore,
+        structures::{BasicResponse, SignedUserAccount},
+        validation::normalize_user_name,
+    },
+    ChainDB,
+};
+
+// Key-value namespaces of the password hints and recovery vaults, keyed by user name
+const PASSWORD_HINTS_NAMESPACE: &str = "__password_hints";
+const RECOVERY_NAMESPACE: &str = "__recovery";
+const RECOVERY_CODES: usize = 8;
+// Invalid codes in a row that lock the recovery of a user for `RECOVERY_LOCKOUT` seconds
+const MAX_FAILED_ATTEMPTS: u32 = 5;
+const RECOVERY_LOCKOUT: u64 = 15 * 60;
+
+/**
+ * Account created along with its recovery codes. The codes are shown only once, store them in
+ * a safe place
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoverableAccount {
+    pub account: SignedUserAccount,
+    pub recovery_codes: Vec<String>,
+}
+
+/**
+ * Recovery codes (hashed) of a user and the id of its account. The password is not kept: a
+ * verified code authorizes a password reset made by the server
+ */
+#[derive(Debug, Serialize, Deserialize)]
+struct RecoveryVault {
+    user_id: String,
+    salt: String,
+    code_hashes: Vec<String>,
+    #[serde(default)]
+    failed_attempts: u32,
+    // Unix timestamp (seconds)
+    #[serde(default)]
+    locked_until: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn error<D>(error_msg: String) -> BasicResponse<D> {
+    BasicResponse {
+        success: false,
+        error_msg,
+        data: None,
+    }
+}
+
+fn vaults(db: &ChainDB) -> KeyValueStore {
+    KeyValueStore::internal(db, RECOVERY_NAMESPACE)
+}
+
+pub(crate) fn hash_code(salt: &str, code: &str) -> String {
+    // Codes are accepted with or without dashes and in any case
+    let code: String = code
+        .chars()
+        .filter(|c| *c != '-')
+        .flat_map(char::to_lowercase)
+        .collect();
+    sha256::digest(format!("{}{}", salt, code))
+}
+
+pub(crate) fn generate_code() -> String {
+    let charset = "abcdefghijkmnpqrstuvwxyz23456789";
+    let code = random_string::generate(12, charset);
+    format!("{}-{}-{}", &code[0..4], &code[4..8], &code[8..12])
+}
+
+/**
+ * Keep the password hint of a user, so it can be read back
+ */
+pub async fn store_password_hint(db: &ChainDB, user_name: &str, hint: &str) {
+    let user_name = &normalize_user_name(user_name);
+    let _ = KeyValueStore::internal(db, PASSWORD_HINTS_NAMESPACE)
+        .set(user_name, &hint.to_string())
+        .await;
+}
+
+pub async fn get_password_hint(db: &ChainDB, user_name: &str) -> BasicResponse<String> {
+    let user_name = &normalize_user_name(user_name);
+    match KeyValueStore::internal(db, PASSWORD_HINTS_NAMESPACE)
+        .get::<String>(user_name)
+        .await
+    {
+        Some(hint) => BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(hint),
+        },
+        None => error("Password hint not found".to_string()),
+    }
+}
+
+/**
+ * Generate new recovery codes for a user, replacing the previous ones. The password is verified
+ * first
+ */
+pub async fn create_recovery_codes(
+    db: &ChainDB,
+    user_name: &str,
+    password: &str,
+) -> BasicResponse<Vec<String>> {
+    let account = db.get_user_account(user_name, password).await;
+    let Some(account) = account.data else {
+        return error(account.error_msg);
+    };
+
+    let codes: Vec<String> = (0..RECOVERY_CODES).map(|_| generate_code()).collect();
+    let salt = random_string::generate(16, "abcdefghijklmnopqrstuvwxyz0123456789");
+
+    let vault = RecoveryVault {
+        user_id: account.id,
+        code_hashes: codes.iter().map(|code| hash_code(&salt, code)).collect(),
+        salt,
+        failed_attempts: 0,
+        locked_until: 0,
+    };
+
+    let stored = vaults(db)
+        .set(&normalize_user_name(user_name), &vault)
+        .await;
+    if !stored.success {
+        return error(stored.error_msg);
+    }
+
+    BasicResponse {
+        success: true,
+        error_msg: String::new(),
+        data: Some(codes),
+    }
+}
+
+/**
+ * Keep the recovery vault of a user pointing to its account after the account got a new id
+ */
+pub(crate) async fn update_recovery_account(db: &ChainDB, user_name: &str, user_id: &str) {
+    let user_name = &normalize_user_name(user_name);
+    let store = vaults(db);
+    let Some(mut vault) = store.get::<RecoveryVault>(user_name).await else {
+        return;
+    };
+
+    if vault.user_id != user_id {
+        vault.user_id = user_id.to_string();
+        let _ = store.set(user_name, &vault).await;
+    }
+}
+
+/**
+ * Reset the password of a user using one of its recovery codes. Each code can be used once.
+ * After `MAX_FAILED_ATTEMPTS` invalid codes in a row, the recovery of the user is locked for a
+ * while
+ */
+pub async fn recover_account(
+    db: &ChainDB,
+    user_name: &str,
+    recovery_code: &str,
+    new_password: &str,
+) -> BasicResponse<SignedUserAccount> {
+    let user_name = &normalize_user_name(user_name);
+    // Attempts of this process are counted one at a time
+    let _guard = db.locks.lock(&format!("recovery:{}", user_name)).await;
+
+    let invalid = "Invalid recovery code".to_string();
+    let store = vaults(db);
+    let Some(mut vault) = store.get::<RecoveryVault>(user_name).await else {
+        return error(invalid);
+    };
+
+    let now = now();
+    if vault.locked_until > now {
+        return error(format!(
+            "Too many invalid recovery codes, try again in {} seconds",
+            vault.locked_until - now
+        ));
+    }
+
+    let hash = hash_code(&vault.salt, recovery_code);
+    if !vault.code_hashes.contains(&hash) {
+        vault.failed_attempts += 1;
+        if vault.failed_attempts >= MAX_FAILED_ATTEMPTS {
+            vault.failed_attempts = 0;
+            vault.locked_until = now + RECOVERY_LOCKOUT;
+        }
+        let _ = store.set(user_name, &vault).await;
+        return error(invalid);
+    }
+
+    let account = credentials::reset_password(db, user_name, &vault.user_id, new_password).await;
+    if !account.success {
+        return account;
+    }
+
+    // Read again, as the password reset updated the vault
+    if let Some(mut vault) = store.get::<RecoveryVault>(user_name).await {
+        vault.code_hashes.retain(|code_hash| code_hash != &hash);
+        vault.failed_attempts = 0;
+        let _ = store.set(user_name, &vault).await;
+    }
+
+    account
+}
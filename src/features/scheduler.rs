@@ -0,0 +1,367 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::{sync::OwnedMutexGuard, task::JoinHandle};
+
+use crate::{
+    features::{
+        batch::Transfer,
+        idempotency::{settlement_status, SettlementStatus},
+        log::AppendLog,
+        structures::BasicResponse,
+        table::Table,
+    },
+    ChainDB,
+};
+
+// Table holding the schedules and log holding the outcome of every run
+const SCHEDULES_TABLE: &str = "__transfer_schedules";
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(10);
+// Checks in which a failed run is retried before it's recorded as failed and skipped
+const MAX_RUN_ATTEMPTS: u32 = 3;
+// Runs of one schedule made per check, so a long downtime doesn't make all the missed runs at once
+const DEFAULT_MAX_RUNS_PER_CHECK: u64 = 10;
+
+/**
+ * Transfer made once or periodically. Times are unix timestamps (seconds)
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferSchedule {
+    pub id: String,
+    pub transfer: Transfer,
+    pub next_run_at: u64,
+    // Seconds between two runs. `None` for a transfer made only once
+    pub every: Option<u64>,
+    // Runs left. `None` for a transfer repeated until it's canceled
+    pub remaining: Option<u64>,
+    // Sequence of the next run, used to build its transfer id
+    pub occurrence: u64,
+    // Failed attempts of the next run
+    pub attempts: u32,
+    pub canceled: bool,
+}
+
+impl TransferSchedule {
+    pub fn is_finished(&self) -> bool {
+        self.canceled || self.remaining == Some(0)
+    }
+
+    fn transfer_id(&self) -> String {
+        format!("schedule:{}:{}", self.id, self.occurrence)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Schedules {
+    schedules: Vec<TransferSchedule>,
+}
+
+/**
+ * Outcome of a scheduled transfer
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledRun {
+    pub schedule_id: String,
+    pub occurrence: u64,
+    pub transfer_id: String,
+    pub due_at: u64,
+    pub run_at: u64,
+    pub success: bool,
+    pub error_msg: String,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn error<D>(error_msg: String) -> BasicResponse<D> {
+    BasicResponse {
+        success: false,
+        error_msg,
+        data: None,
+    }
+}
+
+/**
+ * Scheduler of transfers. Schedules are stored in a table, so a new scheduler continues from
+ * where the previous one stopped. Each run is made with a transfer id built from the schedule
+ * and its occurrence, so a run interrupted before being recorded is never transferred twice.
+ *
+ * Runs missed while no scheduler was running are made when it starts again, at most
+ * `max_runs_per_check` of each schedule per check. Schedulers of the same process (and clones
+ * of the same `ChainDB`) share a lock of the schedules table. Changes made by other processes
+ * in between are detected, so the check fails instead of overwriting them, but only one
+ * scheduler per database should be running.
+ */
+#[derive(Clone, Debug)]
+pub struct TransferScheduler {
+    db: ChainDB,
+    poll_interval: Duration,
+    max_runs_per_check: u64,
+}
+
+impl TransferScheduler {
+    pub fn new(db: &ChainDB) -> Self {
+        Self {
+            db: db.clone(),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            max_runs_per_check: DEFAULT_MAX_RUNS_PER_CHECK,
+        }
+    }
+
+    /**
+     * Time between two checks of the due schedules
+     */
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /**
+     * Maximum runs of one schedule made per check. Missed runs beyond it are made on the next
+     * checks
+     */
+    pub fn with_max_runs_per_check(mut self, max_runs_per_check: u64) -> Self {
+        self.max_runs_per_check = max_runs_per_check.max(1);
+        self
+    }
+
+    // Shared by every handle of the same `ChainDB`
+    async fn lock(&self) -> OwnedMutexGuard<()> {
+        self.db.locks.lock(SCHEDULES_TABLE).await
+    }
+
+    fn runs_log(&self) -> AppendLog<ScheduledRun> {
+        AppendLog::new(&self.db, SCHEDULES_TABLE)
+    }
+
+    async fn table(&self) -> Table<Schedules> {
+        Table::get(&self.db, SCHEDULES_TABLE, Schedules::default).await
+    }
+
+    async fn persist(&self, table: &Table<Schedules>) -> BasicResponse<String> {
+        table.persist_checked().await
+    }
+
+    async fn add(
+        &self,
+        transfer: Transfer,
+        run_at: u64,
+        every: Option<u64>,
+        remaining: Option<u64>,
+    ) -> BasicResponse<TransferSchedule> {
+        let charset = "abcdefghijklmnopqrstuvwxyz0123456789";
+        let schedule = TransferSchedule {
+            id: random_string::generate(16, charset),
+            transfer,
+            next_run_at: run_at,
+            every,
+            remaining,
+            occurrence: 0,
+            attempts: 0,
+            canceled: false,
+        };
+
+        let _guard = self.lock().await;
+        let mut table = self.table().await;
+        table.table.schedules.push(schedule.clone());
+
+        let persisted = self.persist(&table).await;
+        if !persisted.success {
+            return error(persisted.error_msg);
+        }
+
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(schedule),
+        }
+    }
+
+    /**
+     * Schedule a transfer to be made once at the given time
+     */
+    pub async fn schedule_once(
+        &self,
+        transfer: Transfer,
+        run_at: u64,
+    ) -> BasicResponse<TransferSchedule> {
+        self.add(transfer, run_at, None, Some(1)).await
+    }
+
+    /**
+     * Schedule a transfer to be made periodically, starting at `first_run_at`. It's repeated
+     * `occurrences` times or until it's canceled if no limit is given
+     */
+    pub async fn schedule_recurring(
+        &self,
+        transfer: Transfer,
+        first_run_at: u64,
+        every: Duration,
+        occurrences: Option<u64>,
+    ) -> BasicResponse<TransferSchedule> {
+        if every.as_secs() == 0 {
+            return error("Interval must be at least one second".to_string());
+        }
+
+        self.add(transfer, first_run_at, Some(every.as_secs()), occurrences)
+            .await
+    }
+
+    /**
+     * Stop a schedule. Runs already made are kept
+     */
+    pub async fn cancel(&self, schedule_id: &str) -> BasicResponse<TransferSchedule> {
+        let _guard = self.lock().await;
+        let mut table = self.table().await;
+
+        let Some(schedule) = table
+            .table
+            .schedules
+            .iter_mut()
+            .find(|schedule| schedule.id == schedule_id)
+        else {
+            return error("Schedule not found".to_string());
+        };
+        schedule.canceled = true;
+        let schedule = schedule.clone();
+
+        let persisted = self.persist(&table).await;
+        if !persisted.success {
+            return error(persisted.error_msg);
+        }
+
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(schedule),
+        }
+    }
+
+    /**
+     * List all the schedules, including the finished ones
+     */
+    pub async fn schedules(&self) -> Vec<TransferSchedule> {
+        self.table().await.table.schedules
+    }
+
+    /**
+     * Get the outcomes of the runs of a schedule, from the most old to the most recent
+     */
+    pub async fn runs(&self, schedule_id: &str) -> Vec<ScheduledRun> {
+        let log = self.runs_log();
+        log.read(0..log.len().await)
+            .await
+            .into_iter()
+            .map(|item| item.item)
+            .filter(|run| run.schedule_id == schedule_id)
+            .collect()
+    }
+
+    /**
+     * Make the due transfers once, including the ones missed while no scheduler was running (up
+     * to `max_runs_per_check` of each schedule). A failed run is retried on the next checks
+     * before it's recorded as failed and skipped. A run whose outcome is unknown waits until
+     * it's resolved (see `ChainDB::resolve_transfer`). Returns the recorded outcomes
+     */
+    pub async fn run_due(&self) -> BasicResponse<Vec<ScheduledRun>> {
+        let _guard = self.lock().await;
+        let mut table = self.table().await;
+        let log = self.runs_log();
+        let mut runs = vec![];
+        let mut changed = false;
+
+        for schedule in table.table.schedules.iter_mut() {
+            let mut made = 0;
+            while !schedule.is_finished()
+                && schedule.next_run_at <= now()
+                && made < self.max_runs_per_check
+            {
+                made += 1;
+                let transfer_id = schedule.transfer_id();
+                let transfer = &schedule.transfer;
+                let response = self
+                    .db
+                    .transfer_units_with_id(
+                        &transfer_id,
+                        &transfer.from,
+                        &transfer.to,
+                        transfer.units,
+                        transfer.memo.clone(),
+                    )
+                    .await;
+
+                // Never sent again, it may have been made
+                if !response.success
+                    && settlement_status(&self.db, &transfer_id).await
+                        == Some(SettlementStatus::Pending)
+                {
+                    break;
+                }
+
+                // Rejected transfers can be retried with the same transfer id
+                if !response.success {
+                    schedule.attempts += 1;
+                    changed = true;
+                    if schedule.attempts < MAX_RUN_ATTEMPTS {
+                        break;
+                    }
+                }
+
+                let run = ScheduledRun {
+                    schedule_id: schedule.id.clone(),
+                    occurrence: schedule.occurrence,
+                    transfer_id,
+                    due_at: schedule.next_run_at,
+                    run_at: now(),
+                    success: response.success,
+                    error_msg: response.error_msg,
+                };
+                let _ = log.append(&run).await;
+                runs.push(run);
+                changed = true;
+
+                schedule.occurrence += 1;
+                schedule.attempts = 0;
+                schedule.remaining = schedule.remaining.map(|remaining| remaining - 1);
+                if let Some(every) = schedule.every {
+                    schedule.next_run_at = schedule.next_run_at.saturating_add(every);
+                }
+            }
+        }
+
+        if changed {
+            let persisted = self.persist(&table).await;
+            if !persisted.success {
+                return error(persisted.error_msg);
+            }
+        }
+
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(runs),
+        }
+    }
+
+    /**
+     * Run the scheduler in a tokio task. It checks the due transfers at every poll interval
+     * until the task is aborted. A check that fails (or panics, e.g. the server can't be
+     * reached) is retried at the next interval
+     */
+    pub fn start(&self) -> JoinHandle<()> {
+        let scheduler = self.clone();
+
+        tokio::spawn(async move {
+            loop {
+                // Each check runs in its own task, so a panic ends only that check
+                let check = scheduler.clone();
+                let _ = tokio::spawn(async move { check.run_due().await }).await;
+                tokio::time::sleep(scheduler.poll_interval).await;
+            }
+        })
+    }
+}
@@ -0,0 +1,443 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    features::{
+        kv::KeyValueStore,
+        profile,
+        structures::{BasicResponse, SignedUserAccount},
+        table::Table,
+        token::{self, SessionClaims},
+        transfer_query::TransferQuery,
+        two_factor::{self, TwoFactorEnrollment},
+        units::Units,
+    },
+    ChainDB,
+};
+
+pub const DEFAULT_SESSION_DURATION: Duration = Duration::from_secs(60 * 60);
+// Key-value namespace listing the tables owned by each user
+const USER_TABLES_NAMESPACE: &str = "__user_tables";
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn error<D>(error_msg: String) -> BasicResponse<D> {
+    BasicResponse {
+        success: false,
+        error_msg,
+        data: None,
+    }
+}
+
+/**
+ * Name of a table owned by a user
+ */
+pub fn user_table_name(user_id: &str, table_name: &str) -> String {
+    format!("{}:{}", user_id, table_name)
+}
+
+/**
+ * Names of the tables owned by a user (without the user prefix)
+ */
+pub async fn user_tables(db: &ChainDB, user_id: &str) -> Vec<String> {
+    KeyValueStore::internal(db, USER_TABLES_NAMESPACE)
+        .get(user_id)
+        .await
+        .unwrap_or_default()
+}
+
+/**
+ * Record a table owned by a user, so it can be found when the account changes. Registrations
+ * made through the same `ChainDB` (and its clones) are serialized, so none of them is lost
+ */
+pub async fn register_user_table(
+    db: &ChainDB,
+    user_id: &str,
+    table_name: &str,
+) -> BasicResponse<String> {
+    let _guard = db
+        .locks
+        .lock(&format!("{}:{}", USER_TABLES_NAMESPACE, user_id))
+        .await;
+
+    let mut tables = user_tables(db, user_id).await;
+    if tables.iter().any(|name| name == table_name) {
+        return BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: None,
+        };
+    }
+
+    tables.push(table_name.to_string());
+    let registered = KeyValueStore::internal(db, USER_TABLES_NAMESPACE)
+        .set(user_id, &tables)
+        .await;
+    if !registered.success {
+        return error(format!(
+            "Table {} could not be registered for user {}: {}",
+            table_name, user_id, registered.error_msg
+        ));
+    }
+
+    registered
+}
+
+/**
+ * Session of a signed in user. Every call is made on behalf of the session account.
+ *
+ * If the user has two-factor authentication enabled, the session only works after
+ * `verify_two_factor`.
+ *
+ * A session stops working once it expires or after `logout`. Clones share the same session,
+ * so logging out one of them logs out all of them.
+ */
+#[derive(Clone, Debug)]
+pub struct UserSession {
+    account: SignedUserAccount,
+    // Unix timestamp (seconds)
+    expires_at: u64,
+    logged_out: Arc<AtomicBool>,
+    // Set while a two-factor code is needed to complete the login
+    two_factor_pending: Arc<AtomicBool>,
+    db: ChainDB,
+}
+
+impl UserSession {
+    pub(crate) fn new(
+        db: &ChainDB,
+        account: SignedUserAccount,
+        duration: Duration,
+        two_factor_pending: bool,
+    ) -> Self {
+        Self {
+            account,
+            expires_at: now().saturating_add(duration.as_secs()),
+            logged_out: Arc::new(AtomicBool::new(false)),
+            two_factor_pending: Arc::new(AtomicBool::new(two_factor_pending)),
+            db: db.clone(),
+        }
+    }
+
+    // Session of a verified token, see `ChainDB::resume_session`
+    pub(crate) fn resume(db: &ChainDB, account: SignedUserAccount, expires_at: u64) -> Self {
+        Self {
+            account,
+            expires_at,
+            logged_out: Arc::new(AtomicBool::new(false)),
+            two_factor_pending: Arc::new(AtomicBool::new(false)),
+            db: db.clone(),
+        }
+    }
+
+    /**
+     * Signed account, as it was on the last refresh
+     */
+    pub fn account(&self) -> &SignedUserAccount {
+        &self.account
+    }
+
+    pub fn user_id(&self) -> &str {
+        &self.account.id
+    }
+
+    pub fn expires_at(&self) -> u64 {
+        self.expires_at
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.check_active().is_ok()
+    }
+
+    /**
+     * Whether a two-factor code is needed to complete the login (see `verify_two_factor`)
+     */
+    pub fn needs_two_factor(&self) -> bool {
+        self.two_factor_pending.load(Ordering::SeqCst)
+    }
+
+    fn check_signed_in(&self) -> Result<(), String> {
+        if self.logged_out.load(Ordering::SeqCst) {
+            return Err("Session was logged out".to_string());
+        }
+        if now() >= self.expires_at {
+            return Err("Session has expired".to_string());
+        }
+
+        Ok(())
+    }
+
+    fn check_active(&self) -> Result<(), String> {
+        self.check_signed_in()?;
+        if self.needs_two_factor() {
+            return Err("Two-factor verification is required".to_string());
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Complete the login of a user with two-factor authentication, using a code of its
+     * authenticator app or one of its backup codes. Failed codes are counted for the account,
+     * not for the session: after 5 of them in a row no code is accepted for 15 minutes, even
+     * from new logins, and the session is logged out
+     */
+    pub async fn verify_two_factor(&self, code: &str) -> BasicResponse<SignedUserAccount> {
+        if let Err(error_msg) = self.check_signed_in() {
+            return error(error_msg);
+        }
+
+        if self.needs_two_factor() {
+            let verified =
+                two_factor::verify(&self.db, &self.account.id, &self.account.user_name, code).await;
+            if let Err(error_msg) = verified {
+                // Failures are counted for the account, a locked account logs the session out
+                if two_factor::is_locked(&self.db, &self.account.id).await {
+                    self.logout();
+                    return error(format!("{}. The session was logged out", error_msg));
+                }
+                return error(error_msg);
+            }
+            self.two_factor_pending.store(false, Ordering::SeqCst);
+        }
+
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(self.account.clone()),
+        }
+    }
+
+    /**
+     * Start enabling two-factor authentication for the session account. Add the returned
+     * secret to an authenticator app and confirm one of its codes with `confirm_two_factor`
+     */
+    pub async fn enroll_two_factor(&self, issuer: &str) -> BasicResponse<TwoFactorEnrollment> {
+        if let Err(error_msg) = self.check_active() {
+            return error(error_msg);
+        }
+
+        two_factor::enroll(&self.db, &self.account.id, &self.account.user_name, issuer).await
+    }
+
+    /**
+     * Enable two-factor authentication with a code of the authenticator app. Returns the backup
+     * codes, they are shown only once
+     */
+    pub async fn confirm_two_factor(&self, code: &str) -> BasicResponse<Vec<String>> {
+        if let Err(error_msg) = self.check_active() {
+            return error(error_msg);
+        }
+
+        two_factor::confirm(&self.db, &self.account.id, &self.account.user_name, code).await
+    }
+
+    /**
+     * Disable two-factor authentication, after checking a code of the authenticator app or a
+     * backup code
+     */
+    pub async fn disable_two_factor(&self, code: &str) -> BasicResponse<String> {
+        if let Err(error_msg) = self.check_active() {
+            return error(error_msg);
+        }
+
+        two_factor::disable(&self.db, &self.account.id, &self.account.user_name, code).await
+    }
+
+    /**
+     * Fetch the account again, e.g. to get its current balance
+     */
+    pub async fn refresh(&mut self) -> BasicResponse<SignedUserAccount> {
+        if let Err(error_msg) = self.check_active() {
+            return error(error_msg);
+        }
+
+        let account = self.db.get_user_account_by_id(&self.account.id).await;
+        if let Some(data) = account.data.as_ref() {
+            self.account = data.clone();
+        }
+
+        account
+    }
+
+    /**
+     * Transfer units from the session account
+     */
+    pub async fn transfer(
+        &self,
+        to: &str,
+        units: Units,
+        memo: Option<String>,
+    ) -> BasicResponse<String> {
+        if let Err(error_msg) = self.check_active() {
+            return error(error_msg);
+        }
+
+        self.db
+            .transfer_units_with_memo(&self.account.id, to, units, memo)
+            .await
+    }
+
+    /**
+     * Transfer units from the session account using a client generated transfer id
+     */
+    pub async fn transfer_with_id(
+        &self,
+        transfer_id: &str,
+        to: &str,
+        units: Units,
+        memo: Option<String>,
+    ) -> BasicResponse<String> {
+        if let Err(error_msg) = self.check_active() {
+            return error(error_msg);
+        }
+
+        self.db
+            .transfer_units_with_id(transfer_id, &self.account.id, to, units, memo)
+            .await
+    }
+
+    /**
+     * Query the transfer records of the session account
+     */
+    pub fn transfers(&self) -> BasicResponse<TransferQuery> {
+        if let Err(error_msg) = self.check_active() {
+            return error(error_msg);
+        }
+
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(self.db.transfers(&self.account.id)),
+        }
+    }
+
+    /**
+     * Initialize a table owned by the session account. Other users have their own table with
+     * the same name
+     */
+    pub async fn get_table<Model: DeserializeOwned + Serialize>(
+        &self,
+        table_name: &str,
+        get_model_instance: fn() -> Model,
+    ) -> BasicResponse<Table<Model>> {
+        if let Err(error_msg) = self.check_active() {
+            return error(error_msg);
+        }
+
+        let registered = register_user_table(&self.db, &self.account.id, table_name).await;
+        if !registered.success {
+            return error(registered.error_msg);
+        }
+
+        let table_name = user_table_name(&self.account.id, table_name);
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(Table::get(&self.db, &table_name, get_model_instance).await),
+        }
+    }
+
+    /**
+     * Read the profile of the session account
+     */
+    pub async fn get_profile<Profile: DeserializeOwned>(&self) -> BasicResponse<Profile> {
+        if let Err(error_msg) = self.check_active() {
+            return error(error_msg);
+        }
+
+        profile::get_profile(&self.db, &self.account.id).await
+    }
+
+    /**
+     * Replace the profile of the session account.
+     *
+     * Profiles are written through the session of the user, but nothing prevents other writes:
+     * the profile is a plain table, so anyone with the database access key can write the
+     * profile of any user (e.g. `ChainDB::get_table("{user_id}:__profile")` and `persist`). Don't
+     * trust a profile as if only its user could have written it
+     */
+    pub async fn set_profile<Profile: Serialize>(
+        &self,
+        profile: &Profile,
+    ) -> BasicResponse<String> {
+        if let Err(error_msg) = self.check_active() {
+            return error(error_msg);
+        }
+
+        profile::set_profile(&self.db, &self.account.id, profile).await
+    }
+
+    /**
+     * Change the profile of the session account, starting from `get_model_instance` if it has
+     * none yet. Returns the updated profile
+     */
+    pub async fn update_profile<Profile: DeserializeOwned + Serialize>(
+        &self,
+        get_model_instance: fn() -> Profile,
+        update: impl FnOnce(&mut Profile),
+    ) -> BasicResponse<Profile> {
+        if let Err(error_msg) = self.check_active() {
+            return error(error_msg);
+        }
+
+        let current = profile::get_profile(&self.db, &self.account.id).await;
+        let mut profile = current.data.unwrap_or_else(get_model_instance);
+        update(&mut profile);
+
+        let stored = self.set_profile(&profile).await;
+        if !stored.success {
+            return error(stored.error_msg);
+        }
+
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(profile),
+        }
+    }
+
+    /**
+     * Issue a signed token of the session, expiring along with it. Web backends can keep the
+     * token instead of the password and get the session back with `ChainDB::resume_session`.
+     *
+     * Tokens are stateless: logging out doesn't revoke the tokens already issued.
+     */
+    pub fn issue_token(&self) -> BasicResponse<String> {
+        if let Err(error_msg) = self.check_active() {
+            return error(error_msg);
+        }
+
+        let claims = SessionClaims {
+            sub: self.account.id.clone(),
+            user_name: self.account.user_name.clone(),
+            iat: now(),
+            exp: self.expires_at,
+        };
+
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(token::issue(&self.db, &claims)),
+        }
+    }
+
+    /**
+     * End the session. Any following call fails, including the ones of its clones
+     */
+    pub fn logout(&self) {
+        self.logged_out.store(true, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,223 @@
+use std::ops::{Bound, RangeBounds};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    features::{
+        structures::{BasicResponse, TransferUnitsRegistry},
+        units::Units,
+    },
+    ChainDB,
+};
+
+/**
+ * Transfer of an account statement with the balance right after it
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatementLine {
+    pub transfer: TransferUnitsRegistry,
+    pub incoming: bool,
+    pub balance_after: Units,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountStatement {
+    pub user_id: String,
+    pub opening_balance: Units,
+    pub closing_balance: Units,
+    pub total_in: Units,
+    pub total_out: Units,
+    pub lines: Vec<StatementLine>,
+}
+
+fn error<D>(error_msg: String) -> BasicResponse<D> {
+    BasicResponse {
+        success: false,
+        error_msg,
+        data: None,
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(',') || value.contains('"') || value.contains('\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+impl AccountStatement {
+    /**
+     * Net change of the balance in the period (total in - total out)
+     */
+    pub fn net(&self) -> i128 {
+        self.total_in.amount() as i128 - self.total_out.amount() as i128
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("timestamp,block_height,from,to,direction,units,balance,memo\n");
+
+        for line in self.lines.iter() {
+            let transfer = &line.transfer;
+            let row = [
+                transfer
+                    .timestamp
+                    .map(|t| t.to_string())
+                    .unwrap_or_default(),
+                transfer
+                    .block_height
+                    .map(|h| h.to_string())
+                    .unwrap_or_default(),
+                transfer.from.clone(),
+                transfer.to.clone(),
+                if line.incoming { "in" } else { "out" }.to_string(),
+                transfer.units.to_string(),
+                line.balance_after.to_string(),
+                transfer.memo.clone().unwrap_or_default(),
+            ];
+            let row: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+            csv.push_str(&row.join(","));
+            csv.push('\n');
+        }
+
+        csv
+    }
+}
+
+fn is_before(timestamp: u64, range: &impl RangeBounds<u64>) -> bool {
+    match range.start_bound() {
+        Bound::Included(start) => timestamp < *start,
+        Bound::Excluded(start) => timestamp <= *start,
+        Bound::Unbounded => false,
+    }
+}
+
+/**
+ * Build the statement of an account for the given period (transfer timestamps). The balance
+ * history is computed backwards from the current balance of the account.
+ *
+ * Timestamps are taken from the records kept by the client (see `transfer_records`). When the
+ * period is bounded, the statement fails if any transfer of the account has no timestamp (e.g. a
+ * transfer made outside this library), since it can't be placed in or out of the period.
+ */
+pub async fn account_statement(
+    db: &ChainDB,
+    user_id: &str,
+    timestamps: impl RangeBounds<u64>,
+) -> BasicResponse<AccountStatement> {
+    let account = db.get_user_account_by_id(user_id).await;
+    let Some(account) = account.data else {
+        return error(account.error_msg);
+    };
+
+    let transfers = db.get_all_transfers_by_user_id(user_id).await;
+    let Some(transfers) = transfers.data else {
+        return error(transfers.error_msg);
+    };
+
+    // Balance change of each transfer
+    let deltas: Vec<i128> = transfers
+        .iter()
+        .map(|transfer| {
+            let units = transfer.units.amount() as i128;
+            let incoming = if transfer.to == user_id { units } else { 0 };
+            let outgoing = if transfer.from == user_id { units } else { 0 };
+            incoming - outgoing
+        })
+        .collect();
+
+    let initial_balance = account.units.amount() as i128 - deltas.iter().sum::<i128>();
+    if initial_balance < 0 {
+        return error("Transfer records do not match the account balance".to_string());
+    }
+
+    let unbounded = matches!(
+        (timestamps.start_bound(), timestamps.end_bound()),
+        (Bound::Unbounded, Bound::Unbounded)
+    );
+
+    let no_timestamp = transfers.iter().filter(|t| t.timestamp.is_none()).count();
+    if !unbounded && no_timestamp > 0 {
+        return error(format!(
+            "{} transfers of user {} have no timestamp, the statement of a period can't be built. Use `..` for the whole history",
+            no_timestamp, user_id
+        ));
+    }
+
+    let mut balance = initial_balance;
+    let mut opening_balance = initial_balance;
+    let mut reached_period = false;
+    let mut lines = vec![];
+
+    for (transfer, delta) in transfers.into_iter().zip(deltas) {
+        balance += delta;
+        if balance < 0 {
+            return error("Transfer records do not match the account balance".to_string());
+        }
+        let balance_after = Units::new(balance as u64);
+
+        let in_period = match transfer.timestamp {
+            _ if unbounded => true,
+            Some(timestamp) => timestamps.contains(&timestamp),
+            // Rejected above
+            None => false,
+        };
+
+        if in_period {
+            reached_period = true;
+            lines.push(StatementLine {
+                incoming: delta > 0 || (delta == 0 && transfer.to == user_id),
+                transfer,
+                balance_after,
+            });
+            continue;
+        }
+
+        let before_period = transfer
+            .timestamp
+            .is_some_and(|timestamp| is_before(timestamp, &timestamps));
+        if before_period && !reached_period {
+            opening_balance = balance;
+        }
+    }
+
+    let mut total_in = Units::ZERO;
+    let mut total_out = Units::ZERO;
+    for line in lines.iter() {
+        if line.transfer.to == user_id {
+            let Some(total) = total_in.checked_add(line.transfer.units) else {
+                return error("Total units received in the period is too large".to_string());
+            };
+            total_in = total;
+        }
+        if line.transfer.from == user_id {
+            let Some(total) = total_out.checked_add(line.transfer.units) else {
+                return error("Total units sent in the period is too large".to_string());
+            };
+            total_out = total;
+        }
+    }
+
+    let opening_balance = Units::new(opening_balance as u64);
+    let closing_balance = lines
+        .last()
+        .map(|line| line.balance_after)
+        .unwrap_or(opening_balance);
+
+    BasicResponse {
+        success: true,
+        error_msg: String::new(),
+        data: Some(AccountStatement {
+            user_id: user_id.to_string(),
+            opening_balance,
+            closing_balance,
+            total_in,
+            total_out,
+            lines,
+        }),
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use super::units::Units;
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub enum TransactionType {
     NONE,
@@ -33,14 +35,14 @@ impl Access {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UserAccount {
     pub user_name: String,
-    pub units: u64, // coins
+    pub units: Units, // coins
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SignedUserAccount {
     pub id: String, // Used to refer the user
     pub user_name: String,
-    pub units: u64,
+    pub units: Units,
 }
 
 impl SignedUserAccount {
@@ -69,9 +71,24 @@ pub struct ContractTransactionData<Model> {
     pub block_height: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TransferUnitsRegistry {
     pub from: String,
     pub to: String,
-    pub units: u64,
+    pub units: Units,
+    // Named asset transferred. `None` means the account's units
+    #[serde(default)]
+    pub asset: Option<String>,
+    // Client generated id, used to make retries safe
+    #[serde(default)]
+    pub transfer_id: Option<String>,
+    // Reason or reference of the transfer
+    #[serde(default)]
+    pub memo: Option<String>,
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    #[serde(default)]
+    pub block_height: Option<u64>,
+    #[serde(default)]
+    pub block_hash: Option<String>,
 }
@@ -1,12 +1,13 @@
-use reqwest::header::CONTENT_TYPE;
+use std::sync::Mutex;
+
 use serde::{de::DeserializeOwned, Serialize};
-use serde_json::{json, Value};
-use std::collections::HashMap;
+use serde_json::Value;
 
 use crate::{
     features::{
-        constants::{CONTRACT_PAYLOAD, CONTRACT_TRANSACTION, CONTRACT_TRANSACTIONS_PAYLOAD},
-        structures::TransactionType,
+        contract::{self, get_last_transaction, get_transactions, post_transaction},
+        structures::{BasicResponse, TransactionType},
+        transaction,
     },
     ChainDB,
 };
@@ -17,53 +18,104 @@ use super::structures::ContractTransactionData;
 pub struct Table<Model> {
     pub table: Model,
     contract_id: String,
+    // Version of the stored data this table was read at (or last persisted), see `version_of`
+    version: Mutex<String>,
     db: ChainDB,
 }
 
+/**
+ * Version of the data stored in a table: a hash of its content. An empty string means nothing
+ * was stored yet
+ */
+pub(crate) fn version_of(data: Option<&Value>) -> String {
+    match data {
+        Some(data) => sha256::digest(data.to_string()),
+        None => String::new(),
+    }
+}
+
+/**
+ * Data currently stored in a table (contract). `None` if nothing was stored yet
+ */
+pub(crate) async fn current_data(db: &ChainDB, contract_id: &str) -> Option<Value> {
+    let contract_data: Option<ContractTransactionData<Value>> =
+        get_last_transaction(db, contract_id).await;
+
+    contract_data
+        .filter(|tx| tx.tx_type == TransactionType::CONTRACT)
+        .map(|tx| tx.data)
+}
+
+/**
+ * Version of the data currently stored in a table (contract)
+ */
+pub(crate) async fn current_version(db: &ChainDB, contract_id: &str) -> String {
+    version_of(current_data(db, contract_id).await.as_ref())
+}
+
+/**
+ * Persist data in a table (contract) only if the stored data is still at the given version
+ */
+pub(crate) async fn persist_at_version<Model: Serialize>(
+    db: &ChainDB,
+    contract_id: &str,
+    version: &str,
+    data: &Model,
+) -> BasicResponse<String> {
+    if current_version(db, contract_id).await != version {
+        return BasicResponse {
+            success: false,
+            error_msg: "Table was changed since it was read, try again".to_string(),
+            data: None,
+        };
+    }
+
+    post_transaction(db, contract_id, data).await
+}
+
+impl<Model> Table<Model> {
+    pub(crate) fn contract_id(&self) -> &str {
+        &self.contract_id
+    }
+
+    pub(crate) fn version(&self) -> String {
+        self.version.lock().unwrap().clone()
+    }
+}
+
 impl<Model: DeserializeOwned + Serialize> Table<Model> {
     pub async fn get(
         db: &ChainDB,
-        table_name: &'static str,
+        table_name: &str,
         get_model_instance: fn() -> Model,
     ) -> Table<Model> {
-        let contract_id = db.access.parse(db.name.to_string(), table_name.to_string());
+        let contract_id = contract::contract_id(db, table_name);
 
-        // Load content from chain
-        let url = format!(
-            "{api}/{path}/{contract_id}/{db_access_key}",
-            api = db.api,
-            path = CONTRACT_PAYLOAD,
-            contract_id = contract_id,
-            db_access_key = db.access_key,
-        );
-        let res_json = reqwest::get(url).await.unwrap().text().await.unwrap();
-
-        // Check if any info was found
-        let data_json_check: HashMap<String, Value> = serde_json::from_str(&res_json).unwrap();
-        let tx_type_check = data_json_check.get("tx_type").unwrap().as_str().unwrap();
-        if tx_type_check == "NONE" {
-            return Self {
-                contract_id: contract_id.clone(),
-                table: get_model_instance(),
-                db: db.clone(),
-            }
-        }
+        // Finish the transactions interrupted while writing this table. If they can't be
+        // finished now, the table is loaded as it is and they are tried again on the next load
+        let _ = transaction::recover_pending_commits(db, Some(&contract_id)).await;
 
-        let contract_data_json: ContractTransactionData<Model> = serde_json::from_str(&res_json).unwrap();
+        // Load content from chain
+        let contract_data: Option<ContractTransactionData<Value>> =
+            get_last_transaction(db, &contract_id).await;
 
         // If there's already a table (contract) with data, then, fetch its data
-        if contract_data_json.tx_type == TransactionType::CONTRACT {
-            return Self {
-                contract_id: contract_id.clone(),
-                table: contract_data_json.data,
-                db: db.clone(),
-            };
+        if let Some(contract_data_json) = contract_data {
+            if contract_data_json.tx_type == TransactionType::CONTRACT {
+                return Self {
+                    contract_id,
+                    version: Mutex::new(version_of(Some(&contract_data_json.data))),
+                    table: serde_json::from_value(contract_data_json.data).unwrap(),
+                    db: db.clone(),
+                };
+            }
         }
 
         // If there's no content for this table (contract), then, create a new table
         Self {
-            contract_id: contract_id.clone(),
+            contract_id,
             table: get_model_instance(),
+            version: Mutex::new(version_of(None)),
             db: db.clone(),
         }
     }
@@ -72,26 +124,26 @@ impl<Model: DeserializeOwned + Serialize> Table<Model> {
      * Persist table data on chain
      */
     pub async fn persist(&self) {
-        let client = reqwest::Client::new();
-        let url = format!("{api}{route}", api = self.db.api, route = CONTRACT_TRANSACTION);
-
-        let contract_data = serde_json::to_string(&self.table).unwrap();
-
-        let body = json!({
-            "tx_type": TransactionType::CONTRACT,
-            "contract_id": &self.contract_id,
-            "db_access_key": &self.db.access_key,
-            "data": &contract_data
-        });
-
-        let json_body = serde_json::to_string(&body).unwrap();
-
-        let _ = client
-            .post(url)
-            .header(CONTENT_TYPE, "application/json")
-            .body(json_body)
-            .send()
-            .await;
+        let response = post_transaction(&self.db, &self.contract_id, &self.table).await;
+
+        if response.success {
+            let data = serde_json::to_value(&self.table).unwrap();
+            *self.version.lock().unwrap() = version_of(Some(&data));
+        }
+    }
+
+    /**
+     * Persist table data on chain only if the stored data is still at the version this table
+     * was read at. Used by the features that read, change and write back a table
+     */
+    pub(crate) async fn persist_checked(&self) -> BasicResponse<String> {
+        let response =
+            persist_at_version(&self.db, &self.contract_id, &self.version(), &self.table).await;
+        if response.success {
+            let data = serde_json::to_value(&self.table).unwrap();
+            *self.version.lock().unwrap() = version_of(Some(&data));
+        }
+        response
     }
 
     /**
@@ -99,33 +151,13 @@ impl<Model: DeserializeOwned + Serialize> Table<Model> {
      * in a range of depth
      */
     pub async fn get_history(&self, depth: u64) -> Vec<Model> {
-        let url = format!(
-            "{api}{route}/{contract_id}/{db_key}/{depth}",
-            api = self.db.api,
-            route = CONTRACT_TRANSACTIONS_PAYLOAD,
-            contract_id = self.contract_id,
-            db_key = self.db.access_key,
-            depth = depth
-        );
-
-        let res_json = reqwest::get(url).await.expect("Something went wrong!").text().await.unwrap();
-        let contract_data_json_check = serde_json::from_str::<Value>(&res_json).unwrap();
-        let data_arr = contract_data_json_check.as_array().unwrap();
-        let data_tx = data_arr.get(0).unwrap();
-        let data_tx_obj = data_tx.as_object().unwrap();
-
-        // Return empty if theres no data
-        if data_arr.len() == 1 && data_tx_obj.get("tx_type").unwrap() == "NONE" {
-            return vec![];
-        }
-
-        let contract_data_json_list: Vec<ContractTransactionData<Model>> = serde_json::from_str(&res_json).unwrap();
-
-        let transaction_data: Vec<Model> = contract_data_json_list.into_iter()
-        .map(|tx| tx.data)
-        .collect();
+        let contract_data_json_list: Vec<ContractTransactionData<Model>> =
+            get_transactions(&self.db, &self.contract_id, depth).await;
 
         // Return data. Only table fields, e.g.: [{fieldA: 'Hi', filedB: 22}]
-        return transaction_data;
+        contract_data_json_list
+            .into_iter()
+            .map(|tx| tx.data)
+            .collect()
     }
 }
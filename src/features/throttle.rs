@@ -0,0 +1,323 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    features::{
+        kv::KeyValueStore,
+        session::UserSession,
+        structures::{BasicResponse, SignedUserAccount},
+        validation::normalize_user_name,
+    },
+    ChainDB,
+};
+
+// Key-value namespace of the shared counters
+const THROTTLE_NAMESPACE: &str = "__login_throttle";
+// Attempts to update a shared counter changed by another instance at the same time
+const MAX_UPDATE_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ThrottleOptions {
+    // Wait after the first failed login, doubled on each following failure
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // Failed logins in a row that lock the user name (or source)
+    pub lockout_threshold: u32,
+    // Also the time after which the failures are forgotten
+    pub lockout_duration: Duration,
+    // Keep the counters in the database, so every instance of the app shares them
+    pub shared: bool,
+}
+
+impl Default for ThrottleOptions {
+    fn default() -> Self {
+        Self {
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            lockout_threshold: 10,
+            lockout_duration: Duration::from_secs(15 * 60),
+            shared: false,
+        }
+    }
+}
+
+/**
+ * Failed logins of a user name or source. Times are unix timestamps (milliseconds)
+ */
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct FailedLogins {
+    failures: u32,
+    last_failure_at: u64,
+    retry_at: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+fn error<D>(error_msg: String) -> BasicResponse<D> {
+    BasicResponse {
+        success: false,
+        error_msg,
+        data: None,
+    }
+}
+
+/**
+ * Login throttling. After each failed login of a user name, or from a source (e.g. an IP
+ * address), the next attempt has to wait twice as long. After `lockout_threshold` failures
+ * in a row, logins are locked for `lockout_duration`.
+ *
+ * Each attempt is counted as failed before it's sent, and cleared if it succeeds. Attempts made
+ * before the wait is over are rejected without calling the server. Clones share the same
+ * counters.
+ */
+#[derive(Debug, Clone)]
+pub struct LoginThrottle {
+    options: ThrottleOptions,
+    local: Arc<Mutex<HashMap<String, FailedLogins>>>,
+    // Set in shared mode
+    store: Option<KeyValueStore>,
+    db: ChainDB,
+}
+
+impl LoginThrottle {
+    pub(crate) fn new(db: &ChainDB, options: ThrottleOptions) -> Self {
+        Self {
+            options,
+            local: Arc::new(Mutex::new(HashMap::new())),
+            store: options
+                .shared
+                .then(|| KeyValueStore::internal(db, THROTTLE_NAMESPACE)),
+            db: db.clone(),
+        }
+    }
+
+    fn keys(user_name: &str, source: Option<&str>) -> Vec<String> {
+        let mut keys = vec![format!("user:{}", normalize_user_name(user_name))];
+        if let Some(source) = source {
+            keys.push(format!("source:{}", source));
+        }
+        keys
+    }
+
+    async fn get(&self, key: &str) -> FailedLogins {
+        match &self.store {
+            Some(store) => store.get(key).await.unwrap_or_default(),
+            None => self
+                .local
+                .lock()
+                .unwrap()
+                .get(key)
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+
+    // Change the counter of a key. Returns the counter as it was before the change
+    async fn update(
+        &self,
+        key: &str,
+        change: impl Fn(FailedLogins) -> Option<FailedLogins>,
+    ) -> Result<FailedLogins, String> {
+        let Some(store) = &self.store else {
+            let mut local = self.local.lock().unwrap();
+            let current = local.get(key).cloned().unwrap_or_default();
+            match change(current.clone()) {
+                Some(updated) => local.insert(key.to_string(), updated),
+                None => local.remove(key),
+            };
+            return Ok(current);
+        };
+
+        for _ in 0..MAX_UPDATE_ATTEMPTS {
+            let current: Option<FailedLogins> = store.get(key).await;
+            let previous = current.clone().unwrap_or_default();
+            let updated = match change(previous.clone()) {
+                Some(updated) if current.as_ref() == Some(&updated) => return Ok(previous),
+                Some(updated) => updated,
+                None if current.is_none() => return Ok(previous),
+                None => FailedLogins::default(),
+            };
+
+            if store
+                .compare_and_set(key, current.as_ref(), &updated)
+                .await
+                .success
+            {
+                return Ok(previous);
+            }
+        }
+
+        Err("Login attempts could not be counted, try again".to_string())
+    }
+
+    // Time at which the next login is accepted after the given failures in a row
+    fn retry_at(&self, failures: u32, last_failure_at: u64) -> u64 {
+        if failures >= self.options.lockout_threshold {
+            return last_failure_at + self.options.lockout_duration.as_millis() as u64;
+        }
+
+        let delay = self
+            .options
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(failures - 1))
+            .min(self.options.max_delay);
+        last_failure_at + delay.as_millis() as u64
+    }
+
+    fn failed(&self, mut failed: FailedLogins, now: u64) -> FailedLogins {
+        let lockout = self.options.lockout_duration.as_millis() as u64;
+
+        // Failures older than the lockout are forgotten
+        if now.saturating_sub(failed.last_failure_at) > lockout {
+            failed.failures = 0;
+        }
+
+        failed.failures += 1;
+        failed.last_failure_at = now;
+        failed.retry_at = self.retry_at(failed.failures, now);
+
+        failed
+    }
+
+    // Undo the failure counted for an attempt that succeeded
+    fn released(&self, mut failed: FailedLogins) -> Option<FailedLogins> {
+        failed.failures = failed.failures.saturating_sub(1);
+        if failed.failures == 0 {
+            return None;
+        }
+
+        failed.retry_at = self.retry_at(failed.failures, failed.last_failure_at);
+        Some(failed)
+    }
+
+    /**
+     * Time to wait before the next login of the user name (and source) is accepted. `None` if
+     * it can be tried now
+     */
+    pub async fn retry_after(&self, user_name: &str, source: Option<&str>) -> Option<Duration> {
+        let now = now();
+        let mut retry_at = 0;
+
+        for key in Self::keys(user_name, source) {
+            retry_at = retry_at.max(self.get(&key).await.retry_at);
+        }
+
+        (retry_at > now).then(|| Duration::from_millis(retry_at - now))
+    }
+
+    // Count the attempt as failed before it's sent to the server, so attempts made at the same
+    // time are delayed too. Rejected if a key must wait or its counter can't be updated
+    async fn reserve(&self, user_name: &str, source: Option<&str>) -> Result<(), String> {
+        let now = now();
+        let mut reserved = vec![];
+        let mut result = Ok(());
+
+        for key in Self::keys(user_name, source) {
+            // Keys that must wait are left as they are
+            let previous = self
+                .update(&key, |failed| {
+                    if failed.retry_at > now {
+                        Some(failed)
+                    } else {
+                        Some(self.failed(failed, now))
+                    }
+                })
+                .await;
+
+            match previous {
+                Ok(previous) if previous.retry_at > now => {
+                    let wait = Duration::from_millis(previous.retry_at - now);
+                    result = Err(format!(
+                        "Too many failed logins, try again in {} seconds",
+                        wait.as_secs_f64().ceil()
+                    ));
+                    break;
+                }
+                Ok(_) => reserved.push(key),
+                Err(error_msg) => {
+                    result = Err(error_msg);
+                    break;
+                }
+            }
+        }
+
+        if result.is_err() {
+            for key in reserved {
+                let _ = self.update(&key, |failed| self.released(failed)).await;
+            }
+        }
+
+        result
+    }
+
+    async fn record_success(&self, user_name: &str, source: Option<&str>) {
+        for key in Self::keys(user_name, source) {
+            // The failures of a source are kept, a valid login of one user doesn't make the
+            // guesses made on other user names fine
+            let _ = if key.starts_with("user:") {
+                self.update(&key, |_| None).await
+            } else {
+                self.update(&key, |failed| self.released(failed)).await
+            };
+        }
+    }
+
+    /**
+     * Same as `ChainDB::get_user_account`, counting the failed attempts
+     */
+    pub async fn get_user_account(
+        &self,
+        user_name: &str,
+        password: &str,
+        source: Option<&str>,
+    ) -> BasicResponse<SignedUserAccount> {
+        if let Err(error_msg) = self.reserve(user_name, source).await {
+            return error(error_msg);
+        }
+
+        let account = self.db.get_user_account(user_name, password).await;
+        if account.success {
+            self.record_success(user_name, source).await;
+        }
+        account
+    }
+
+    /**
+     * Same as `ChainDB::login`, counting the failed attempts
+     */
+    pub async fn login(
+        &self,
+        user_name: &str,
+        password: &str,
+        source: Option<&str>,
+    ) -> BasicResponse<UserSession> {
+        if let Err(error_msg) = self.reserve(user_name, source).await {
+            return error(error_msg);
+        }
+
+        let session = self.db.login(user_name, password).await;
+        if session.success {
+            self.record_success(user_name, source).await;
+        }
+        session
+    }
+
+    /**
+     * Forget the failed logins of a user name, e.g. after its password was reset
+     */
+    pub async fn reset(&self, user_name: &str) {
+        for key in Self::keys(user_name, None) {
+            let _ = self.update(&key, |_| None).await;
+        }
+    }
+}
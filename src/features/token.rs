@@ -0,0 +1,141 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    features::{session::UserSession, structures::BasicResponse},
+    ChainDB,
+};
+
+/**
+ * Claims of a session token. `sub` is the account id of the user, times are unix timestamps
+ * (seconds)
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionClaims {
+    pub sub: String,
+    pub user_name: String,
+    pub iat: u64,
+    pub exp: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn error<D>(error_msg: String) -> BasicResponse<D> {
+    BasicResponse {
+        success: false,
+        error_msg,
+        data: None,
+    }
+}
+
+// Signing key derived from the database access key, so every instance of the app connected
+// to the same database accepts the tokens of the others
+fn mac(db: &ChainDB) -> Hmac<Sha256> {
+    let key = Sha256::digest(format!("{}:session_tokens", db.access_key));
+    Hmac::<Sha256>::new_from_slice(&key).unwrap()
+}
+
+/**
+ * Issue a token (JWT signed with HS256) of a session, expiring along with it
+ */
+pub(crate) fn issue(db: &ChainDB, claims: &SessionClaims) -> String {
+    let header = json!({ "alg": "HS256", "typ": "JWT" });
+    let signing_input = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(header.to_string()),
+        URL_SAFE_NO_PAD.encode(serde_json::to_string(claims).unwrap())
+    );
+
+    let mut mac = mac(db);
+    mac.update(signing_input.as_bytes());
+    let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+
+    format!("{}.{}", signing_input, signature)
+}
+
+/**
+ * Check the signature and expiry of a session token, returning its claims
+ */
+pub(crate) fn verify(db: &ChainDB, token: &str) -> BasicResponse<SessionClaims> {
+    let invalid = || error("Invalid session token".to_string());
+
+    let parts: Vec<&str> = token.trim().split('.').collect();
+    let [header, claims, signature] = parts[..] else {
+        return invalid();
+    };
+
+    let Ok(signature) = URL_SAFE_NO_PAD.decode(signature) else {
+        return invalid();
+    };
+    let mut mac = mac(db);
+    mac.update(format!("{}.{}", header, claims).as_bytes());
+    // Constant time comparison
+    if mac.verify_slice(&signature).is_err() {
+        return invalid();
+    }
+
+    // Only HS256 is issued, any other algorithm is rejected
+    let header = URL_SAFE_NO_PAD
+        .decode(header)
+        .ok()
+        .and_then(|header| serde_json::from_slice::<Value>(&header).ok());
+    if header
+        .as_ref()
+        .and_then(|h| h.get("alg"))
+        .and_then(Value::as_str)
+        != Some("HS256")
+    {
+        return invalid();
+    }
+
+    let claims = URL_SAFE_NO_PAD
+        .decode(claims)
+        .ok()
+        .and_then(|claims| serde_json::from_slice::<SessionClaims>(&claims).ok());
+    let Some(claims) = claims else {
+        return invalid();
+    };
+
+    if now() >= claims.exp {
+        return error("Session token has expired".to_string());
+    }
+
+    BasicResponse {
+        success: true,
+        error_msg: String::new(),
+        data: Some(claims),
+    }
+}
+
+/**
+ * Get the session of a token, fetching its account. The session expires along with the token
+ */
+pub(crate) async fn resume_session(db: &ChainDB, token: &str) -> BasicResponse<UserSession> {
+    let claims = verify(db, token);
+    let Some(claims) = claims.data else {
+        return error(claims.error_msg);
+    };
+
+    let account = db.get_user_account_by_id(&claims.sub).await;
+    let Some(mut account) = account.data else {
+        return error(account.error_msg);
+    };
+    // The name the user signed in with, the server may keep another one after a password change
+    account.user_name = claims.user_name;
+
+    BasicResponse {
+        success: true,
+        error_msg: String::new(),
+        data: Some(UserSession::resume(db, account, claims.exp)),
+    }
+}
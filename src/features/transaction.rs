@@ -0,0 +1,297 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::OwnedMutexGuard;
+
+use crate::{
+    features::{
+        contract::post_transaction,
+        kv::KeyValueStore,
+        structures::BasicResponse,
+        table::{current_data, current_version, version_of, Table},
+    },
+    ChainDB,
+};
+
+// Key-value namespace of the commits not finished yet, keyed by transaction id
+const PENDING_COMMITS_NAMESPACE: &str = "__pending_commits";
+// Pending commits younger than this may still be in progress in another process, so they are
+// not recovered yet
+const RECOVER_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StagedWrite {
+    contract_id: String,
+    // Version of the table when it was read. The write is applied only over that version
+    expected_version: String,
+    // Data of the table at that version, written back if the commit is undone
+    previous: Option<Value>,
+    data: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingCommit {
+    transaction_id: String,
+    // Unix timestamp (seconds)
+    started_at: u64,
+    writes: Vec<StagedWrite>,
+}
+
+// How a pending commit was finished
+enum Outcome {
+    Applied,
+    // Not applied, the tables already written were set back. Has the reason
+    Undone(String),
+    // Left pending to be finished by the recovery. Has the reason
+    Unfinished(String),
+}
+
+/**
+ * Stages the data of several tables to be persisted together.
+ *
+ * The server has no batch endpoint, so the commit is made in two phases: the staged data is
+ * stored as a pending commit first, then each table is persisted and the pending commit is
+ * removed. Every table is checked before anything is written, and the commit fails if any of
+ * them was changed since it was read.
+ *
+ * If the process stops in the middle, the commit is finished when one of its tables is loaded
+ * again (or by `ChainDB::recover_transactions`): it's applied if its remaining tables are still
+ * at the versions they were read at, otherwise the tables already written are set back.
+ */
+#[derive(Debug)]
+pub struct TableTransaction {
+    db: ChainDB,
+    writes: Vec<StagedWrite>,
+}
+
+impl TableTransaction {
+    pub fn new(db: &ChainDB) -> Self {
+        Self {
+            db: db.clone(),
+            writes: vec![],
+        }
+    }
+
+    /**
+     * Stage the current data of a table
+     */
+    pub fn stage<Model: Serialize>(mut self, table: &Table<Model>) -> Self {
+        self.writes.push(StagedWrite {
+            contract_id: table.contract_id().to_string(),
+            expected_version: table.version(),
+            previous: None,
+            data: serde_json::to_value(&table.table).unwrap(),
+        });
+        self
+    }
+
+    /**
+     * Persist all the staged tables, or none of them. Returns the transaction id
+     */
+    pub async fn commit(mut self) -> BasicResponse<String> {
+        let _guards = lock_tables(&self.db, &self.writes).await;
+
+        // Every table is checked before anything is written
+        for write in self.writes.iter_mut() {
+            let current = current_data(&self.db, &write.contract_id).await;
+            if version_of(current.as_ref()) != write.expected_version {
+                return error(format!(
+                    "Table {} was changed since it was read",
+                    write.contract_id
+                ));
+            }
+            write.previous = current;
+        }
+
+        let charset = "abcdefghijklmnopqrstuvwxyz0123456789";
+        let pending = PendingCommit {
+            transaction_id: random_string::generate(16, charset),
+            started_at: now(),
+            writes: self.writes,
+        };
+
+        let stored = pending_commits(&self.db)
+            .set(&pending.transaction_id, &pending)
+            .await;
+        if !stored.success {
+            return stored;
+        }
+
+        match apply_commit(&self.db, &pending).await {
+            Outcome::Applied => BasicResponse {
+                success: true,
+                error_msg: String::new(),
+                data: Some(pending.transaction_id),
+            },
+            Outcome::Undone(reason) | Outcome::Unfinished(reason) => error(reason),
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn error<D>(error_msg: String) -> BasicResponse<D> {
+    BasicResponse {
+        success: false,
+        error_msg,
+        data: None,
+    }
+}
+
+fn pending_commits(db: &ChainDB) -> KeyValueStore {
+    KeyValueStore::internal(db, PENDING_COMMITS_NAMESPACE)
+}
+
+// Locks of the staged tables, always taken in the same order so two commits can't wait for
+// each other
+async fn lock_tables(db: &ChainDB, writes: &[StagedWrite]) -> Vec<OwnedMutexGuard<()>> {
+    let mut contract_ids: Vec<&str> = writes.iter().map(|w| w.contract_id.as_str()).collect();
+    contract_ids.sort();
+    contract_ids.dedup();
+
+    let mut guards = vec![];
+    for contract_id in contract_ids {
+        guards.push(db.locks.lock(contract_id).await);
+    }
+    guards
+}
+
+async fn apply_commit(db: &ChainDB, pending: &PendingCommit) -> Outcome {
+    // Decided before writing anything: a table changed by another writer undoes the commit
+    let mut versions = vec![];
+    for write in pending.writes.iter() {
+        let current = current_version(db, &write.contract_id).await;
+        let applied = current == version_of(Some(&write.data));
+
+        if !applied && current != write.expected_version {
+            let reason = format!("table {} was changed since it was read", write.contract_id);
+            return undo_commit(db, pending, reason).await;
+        }
+        versions.push(applied);
+    }
+
+    for (write, applied) in pending.writes.iter().zip(versions) {
+        // Already applied before the commit was interrupted
+        if applied {
+            continue;
+        }
+
+        let response = post_transaction(db, &write.contract_id, &write.data).await;
+        if !response.success {
+            return Outcome::Unfinished(format!(
+                "Transaction {} was not fully applied, it will be finished by the recovery: {}",
+                pending.transaction_id, response.error_msg
+            ));
+        }
+    }
+
+    let finished = pending_commits(db).delete(&pending.transaction_id).await;
+    if !finished.success {
+        return Outcome::Unfinished(format!(
+            "Transaction {} was applied, but it could not be set as finished: {}",
+            pending.transaction_id, finished.error_msg
+        ));
+    }
+
+    Outcome::Applied
+}
+
+// Set back the tables already written by a commit that can't be fully applied
+async fn undo_commit(db: &ChainDB, pending: &PendingCommit, reason: String) -> Outcome {
+    let mut not_undone = vec![];
+
+    for write in pending.writes.iter() {
+        let written = version_of(Some(&write.data));
+        // Tables not written by the commit (or changed after it) are left as they are
+        if written == write.expected_version
+            || current_version(db, &write.contract_id).await != written
+        {
+            continue;
+        }
+
+        // A table with no data before can't be emptied again
+        let undone = match &write.previous {
+            Some(previous) => {
+                post_transaction(db, &write.contract_id, previous)
+                    .await
+                    .success
+            }
+            None => false,
+        };
+        if !undone {
+            not_undone.push(write.contract_id.clone());
+        }
+    }
+
+    if !not_undone.is_empty() {
+        return Outcome::Unfinished(format!(
+            "Transaction {} was not applied because {}, and these tables could not be set back: {}",
+            pending.transaction_id,
+            reason,
+            not_undone.join(", ")
+        ));
+    }
+
+    let finished = pending_commits(db).delete(&pending.transaction_id).await;
+    if !finished.success {
+        return Outcome::Unfinished(format!(
+            "Transaction {} was not applied because {}, but it could not be set as finished: {}",
+            pending.transaction_id, reason, finished.error_msg
+        ));
+    }
+
+    Outcome::Undone(format!(
+        "Transaction {} was not applied because {}",
+        pending.transaction_id, reason
+    ))
+}
+
+/**
+ * Finish the commits interrupted in the middle, applying or undoing them. Commits started in
+ * the last minute may still be in progress, so they are skipped. If `contract_id` is given,
+ * only the commits writing that table are finished. Returns the ids of the finished
+ * transactions
+ */
+pub async fn recover_pending_commits(
+    db: &ChainDB,
+    contract_id: Option<&str>,
+) -> BasicResponse<Vec<String>> {
+    let store = pending_commits(db);
+    let mut recovered = vec![];
+    let mut errors = vec![];
+
+    for transaction_id in store.keys().await {
+        let Some(pending) = store.get::<PendingCommit>(&transaction_id).await else {
+            continue;
+        };
+
+        let writes_table = contract_id
+            .is_none_or(|contract_id| pending.writes.iter().any(|w| w.contract_id == contract_id));
+        if !writes_table || now().saturating_sub(pending.started_at) < RECOVER_AFTER.as_secs() {
+            continue;
+        }
+
+        let _guards = lock_tables(db, &pending.writes).await;
+        // Finished by another caller while waiting for the locks
+        if store.get::<PendingCommit>(&transaction_id).await.is_none() {
+            continue;
+        }
+
+        match apply_commit(db, &pending).await {
+            Outcome::Applied | Outcome::Undone(_) => recovered.push(transaction_id),
+            Outcome::Unfinished(reason) => errors.push(reason),
+        }
+    }
+
+    BasicResponse {
+        success: errors.is_empty(),
+        error_msg: errors.join("; "),
+        data: Some(recovered),
+    }
+}
@@ -0,0 +1,320 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    features::{
+        idempotency::{settlement_status, SettlementStatus},
+        kv::KeyValueStore,
+        structures::BasicResponse,
+        table::{current_version, persist_at_version, version_of, Table},
+        units::Units,
+    },
+    ChainDB,
+};
+
+// Key-value namespace where the in-flight operations are journaled
+const OPERATIONS_NAMESPACE: &str = "__transfer_operations";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum OperationStatus {
+    Started,
+    Transferred,
+    Completed,
+    Failed,
+    Compensated,
+}
+
+/**
+ * Journal entry of a "transfer units plus table update" operation
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferOperation {
+    pub id: String,
+    pub from: String,
+    pub to: String,
+    pub units: Units,
+    pub contract_id: String,
+    // Version of the table when the operation started. The data is only persisted over it
+    pub expected_version: String,
+    pub data: Value,
+    pub status: OperationStatus,
+}
+
+impl TransferOperation {
+    // Transfer ids of the two legs, so recovery can check if they were made
+    fn forward_id(&self) -> String {
+        format!("op:{}:forward", self.id)
+    }
+
+    fn compensate_id(&self) -> String {
+        format!("op:{}:compensate", self.id)
+    }
+}
+
+fn journal(db: &ChainDB) -> KeyValueStore {
+    KeyValueStore::internal(db, OPERATIONS_NAMESPACE)
+}
+
+fn error<D>(error_msg: String) -> BasicResponse<D> {
+    BasicResponse {
+        success: false,
+        error_msg,
+        data: None,
+    }
+}
+
+async fn set_status(
+    db: &ChainDB,
+    operation: &mut TransferOperation,
+    status: OperationStatus,
+) -> Result<(), String> {
+    operation.status = status;
+    let journal = journal(db);
+    let stored = journal.set(&operation.id, operation).await;
+    if !stored.success {
+        return Err(format!(
+            "operation {} could not be journaled as {:?}: {}",
+            operation.id, operation.status, stored.error_msg
+        ));
+    }
+
+    // Finished operations leave the journal. Their states are kept in the key history
+    if is_finished(&operation.status) {
+        let removed = journal.delete(&operation.id).await;
+        if !removed.success {
+            return Err(format!(
+                "operation {} could not be removed from the journal: {}",
+                operation.id, removed.error_msg
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn is_finished(status: &OperationStatus) -> bool {
+    !matches!(
+        status,
+        OperationStatus::Started | OperationStatus::Transferred
+    )
+}
+
+/**
+ * Transfer units and persist the table data. The data is only persisted if the table wasn't
+ * changed since it was read. If it can't be persisted, the units are transferred back to the
+ * sender
+ */
+pub async fn transfer_and_persist<Model: Serialize>(
+    db: &ChainDB,
+    from: &str,
+    to: &str,
+    units: Units,
+    table: &Table<Model>,
+) -> BasicResponse<String> {
+    let charset = "abcdefghijklmnopqrstuvwxyz0123456789";
+    let mut operation = TransferOperation {
+        id: random_string::generate(16, charset),
+        from: from.to_string(),
+        to: to.to_string(),
+        units,
+        contract_id: table.contract_id().to_string(),
+        expected_version: table.version(),
+        data: serde_json::to_value(&table.table).unwrap(),
+        status: OperationStatus::Started,
+    };
+
+    let started = journal(db).set(&operation.id, &operation).await;
+    if !started.success {
+        return started;
+    }
+
+    let transfer = db
+        .transfer_units_with_id(&operation.forward_id(), from, to, units, None)
+        .await;
+    if !transfer.success {
+        // If its outcome is unknown, the operation is kept as started for the recovery
+        if settlement_status(db, &operation.forward_id()).await != Some(SettlementStatus::Pending) {
+            if let Err(error_msg) = set_status(db, &mut operation, OperationStatus::Failed).await {
+                return error(format!("{}, and the {}", transfer.error_msg, error_msg));
+            }
+        }
+        return transfer;
+    }
+
+    if let Err(error_msg) = set_status(db, &mut operation, OperationStatus::Transferred).await {
+        return error(format!(
+            "Units were transferred, but the {}. It will be finished by the recovery",
+            error_msg
+        ));
+    }
+    complete_operation(db, &mut operation).await
+}
+
+// Persist the table data of a transferred operation or compensate the transfer
+async fn complete_operation(
+    db: &ChainDB,
+    operation: &mut TransferOperation,
+) -> BasicResponse<String> {
+    // Persisted before the process stopped
+    let persisted =
+        if current_version(db, &operation.contract_id).await == version_of(Some(&operation.data)) {
+            BasicResponse {
+                success: true,
+                error_msg: String::new(),
+                data: None,
+            }
+        } else {
+            persist_at_version(
+                db,
+                &operation.contract_id,
+                &operation.expected_version,
+                &operation.data,
+            )
+            .await
+        };
+
+    if persisted.success {
+        if let Err(error_msg) = set_status(db, operation, OperationStatus::Completed).await {
+            return BasicResponse {
+                success: false,
+                error_msg: format!(
+                    "Units were transferred and the table persisted, but the {}",
+                    error_msg
+                ),
+                data: Some(operation.id.clone()),
+            };
+        }
+        return BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(operation.id.clone()),
+        };
+    }
+
+    let reverse = db
+        .without_transfer_policies()
+        .transfer_units_with_id(
+            &operation.compensate_id(),
+            &operation.to,
+            &operation.from,
+            operation.units,
+            None,
+        )
+        .await;
+
+    if reverse.success {
+        let mut error_msg = format!(
+            "Table could not be persisted, the transfer was reversed: {}",
+            persisted.error_msg
+        );
+        if let Err(journal_error) = set_status(db, operation, OperationStatus::Compensated).await {
+            error_msg = format!("{}. Also, the {}", error_msg, journal_error);
+        }
+        return BasicResponse {
+            success: false,
+            error_msg,
+            data: Some(operation.id.clone()),
+        };
+    }
+
+    BasicResponse {
+        success: false,
+        error_msg: format!(
+            "Table could not be persisted and the transfer could not be reversed, it will be retried on recovery: {}",
+            reverse.error_msg
+        ),
+        data: Some(operation.id.clone()),
+    }
+}
+
+/**
+ * Finish the operations interrupted in the middle. Returns the recovered operations.
+ * Operations with a transfer whose outcome is unknown are kept until it's resolved (see
+ * `ChainDB::resolve_transfer`). They are reported in the error message, along with the
+ * operations whose journal could not be updated
+ */
+pub async fn recover_operations(db: &ChainDB) -> BasicResponse<Vec<TransferOperation>> {
+    let journal = journal(db);
+    let mut recovered = vec![];
+    let mut errors = vec![];
+
+    for id in journal.keys().await {
+        let Some(mut operation) = journal.get::<TransferOperation>(&id).await else {
+            continue;
+        };
+
+        // Finished before it could be removed from the journal
+        if is_finished(&operation.status) {
+            let status = operation.status.clone();
+            match set_status(db, &mut operation, status).await {
+                Ok(()) => recovered.push(operation),
+                Err(error_msg) => errors.push(error_msg),
+            }
+            continue;
+        }
+
+        if operation.status == OperationStatus::Started {
+            // Check if the transfer was made before the process stopped
+            match settlement_status(db, &operation.forward_id()).await {
+                Some(SettlementStatus::Settled) => {
+                    let transferred =
+                        set_status(db, &mut operation, OperationStatus::Transferred).await;
+                    if let Err(error_msg) = transferred {
+                        errors.push(error_msg);
+                        continue;
+                    }
+                }
+                Some(SettlementStatus::Pending) => {
+                    errors.push(unknown_outcome(&operation.forward_id()));
+                    continue;
+                }
+                None | Some(SettlementStatus::Failed) => {
+                    match set_status(db, &mut operation, OperationStatus::Failed).await {
+                        Ok(()) => recovered.push(operation),
+                        Err(error_msg) => errors.push(error_msg),
+                    }
+                    continue;
+                }
+            }
+        }
+
+        match settlement_status(db, &operation.compensate_id()).await {
+            Some(SettlementStatus::Pending) => {
+                errors.push(unknown_outcome(&operation.compensate_id()));
+                continue;
+            }
+            // Reversed before the process stopped, the table data is not persisted
+            Some(SettlementStatus::Settled) => {
+                match set_status(db, &mut operation, OperationStatus::Compensated).await {
+                    Ok(()) => recovered.push(operation),
+                    Err(error_msg) => errors.push(error_msg),
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        // A compensated operation is recovered too. If its journal entry could not be
+        // updated, it's cleaned up by the next recovery
+        let completed = complete_operation(db, &mut operation).await;
+        if !completed.success && operation.status != OperationStatus::Compensated {
+            errors.push(completed.error_msg);
+        }
+        if is_finished(&operation.status) {
+            recovered.push(operation);
+        }
+    }
+
+    BasicResponse {
+        success: errors.is_empty(),
+        error_msg: errors.join("; "),
+        data: Some(recovered),
+    }
+}
+
+fn unknown_outcome(transfer_id: &str) -> String {
+    format!(
+        "transfer {} has an unknown outcome, it must be resolved first",
+        transfer_id
+    )
+}
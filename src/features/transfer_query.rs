@@ -0,0 +1,253 @@
+use std::ops::{Bound, RangeBounds};
+
+use futures::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    features::{
+        structures::{BasicResponse, TransferUnitsRegistry},
+        units::Units,
+    },
+    ChainDB,
+};
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransferDirection {
+    Incoming,
+    Outgoing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    // From the most old to the most recent
+    Ascending,
+    // From the most recent to the most old
+    Descending,
+}
+
+/**
+ * Page of transfer records. Use `next_cursor` to fetch the next page
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransferPage {
+    pub records: Vec<TransferUnitsRegistry>,
+    pub next_cursor: Option<String>,
+}
+
+/**
+ * Query over the transfer records of a user.
+ *
+ * The server returns all the records of a user in one response, so filters and pagination are
+ * applied by the client. Cursors point to the position of a record in the user's history, so
+ * pages are stable while new transfers are made.
+ *
+ * The server sends no timestamp or block information: they are taken from the records kept by
+ * the client (see `transfer_records`). A query with a timestamps or block heights range fails if
+ * a record of the user has no such information (e.g. a transfer made outside this library),
+ * instead of leaving it out.
+ */
+#[derive(Debug, Clone)]
+pub struct TransferQuery {
+    db: ChainDB,
+    user_id: String,
+    direction: Option<TransferDirection>,
+    counterparty: Option<String>,
+    units: (Bound<Units>, Bound<Units>),
+    timestamps: (Bound<u64>, Bound<u64>),
+    block_heights: (Bound<u64>, Bound<u64>),
+    order: SortOrder,
+    limit: usize,
+    cursor: Option<usize>,
+}
+
+fn owned_bounds<T: Copy>(range: impl RangeBounds<T>) -> (Bound<T>, Bound<T>) {
+    (range.start_bound().cloned(), range.end_bound().cloned())
+}
+
+fn is_unbounded<T>(range: &(Bound<T>, Bound<T>)) -> bool {
+    matches!(range, (Bound::Unbounded, Bound::Unbounded))
+}
+
+// Records without the information are rejected before filtering by a bounded range
+fn optional_in_range(value: Option<u64>, range: &(Bound<u64>, Bound<u64>)) -> bool {
+    value.is_none_or(|value| range.contains(&value))
+}
+
+impl TransferQuery {
+    pub fn new(db: &ChainDB, user_id: &str) -> Self {
+        Self {
+            db: db.clone(),
+            user_id: user_id.to_string(),
+            direction: None,
+            counterparty: None,
+            units: (Bound::Unbounded, Bound::Unbounded),
+            timestamps: (Bound::Unbounded, Bound::Unbounded),
+            block_heights: (Bound::Unbounded, Bound::Unbounded),
+            order: SortOrder::Ascending,
+            limit: DEFAULT_PAGE_SIZE,
+            cursor: None,
+        }
+    }
+
+    pub fn direction(mut self, direction: TransferDirection) -> Self {
+        self.direction = Some(direction);
+        self
+    }
+
+    /**
+     * Only transfers sent to or received from the given user
+     */
+    pub fn counterparty(mut self, user_id: &str) -> Self {
+        self.counterparty = Some(user_id.to_string());
+        self
+    }
+
+    pub fn units(mut self, range: impl RangeBounds<Units>) -> Self {
+        self.units = owned_bounds(range);
+        self
+    }
+
+    pub fn timestamps(mut self, range: impl RangeBounds<u64>) -> Self {
+        self.timestamps = owned_bounds(range);
+        self
+    }
+
+    pub fn block_heights(mut self, range: impl RangeBounds<u64>) -> Self {
+        self.block_heights = owned_bounds(range);
+        self
+    }
+
+    pub fn order(mut self, order: SortOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /**
+     * Maximum amount of records per page
+     */
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit.max(1);
+        self
+    }
+
+    /**
+     * Start after the record pointed by the cursor of a previous page. Returns an error if the
+     * cursor is not valid
+     */
+    pub fn after(mut self, cursor: &str) -> Result<Self, String> {
+        let position = cursor
+            .parse()
+            .map_err(|_| format!("Invalid cursor: {}", cursor))?;
+        self.cursor = Some(position);
+        Ok(self)
+    }
+
+    fn matches(&self, record: &TransferUnitsRegistry) -> bool {
+        let direction = match self.direction {
+            Some(TransferDirection::Incoming) => record.to == self.user_id,
+            Some(TransferDirection::Outgoing) => record.from == self.user_id,
+            None => true,
+        };
+
+        let counterparty = match &self.counterparty {
+            Some(user_id) => {
+                (record.from == self.user_id && &record.to == user_id)
+                    || (record.to == self.user_id && &record.from == user_id)
+            }
+            None => true,
+        };
+
+        direction
+            && counterparty
+            && self.units.contains(&record.units)
+            && optional_in_range(record.timestamp, &self.timestamps)
+            && optional_in_range(record.block_height, &self.block_heights)
+    }
+
+    // Matching records after the cursor, in the query order, with their positions
+    async fn remaining(&self) -> Result<Vec<(usize, TransferUnitsRegistry)>, String> {
+        let response = self.db.get_all_transfers_by_user_id(&self.user_id).await;
+        let Some(records) = response.data else {
+            return Err(response.error_msg);
+        };
+
+        let missing = |name: &str, count: usize| {
+            format!(
+                "{} transfer records of user {} have no {}, they can't be filtered by it",
+                count, self.user_id, name
+            )
+        };
+        let no_timestamp = records.iter().filter(|r| r.timestamp.is_none()).count();
+        if !is_unbounded(&self.timestamps) && no_timestamp > 0 {
+            return Err(missing("timestamp", no_timestamp));
+        }
+        let no_block_height = records.iter().filter(|r| r.block_height.is_none()).count();
+        if !is_unbounded(&self.block_heights) && no_block_height > 0 {
+            return Err(missing("block height", no_block_height));
+        }
+
+        let mut matching: Vec<(usize, TransferUnitsRegistry)> = records
+            .into_iter()
+            .enumerate()
+            .filter(|(_, record)| self.matches(record))
+            .collect();
+
+        if self.order == SortOrder::Descending {
+            matching.reverse();
+        }
+
+        Ok(matching
+            .into_iter()
+            .filter(|(position, _)| match (self.cursor, self.order) {
+                (None, _) => true,
+                (Some(cursor), SortOrder::Ascending) => *position > cursor,
+                (Some(cursor), SortOrder::Descending) => *position < cursor,
+            })
+            .collect())
+    }
+
+    /**
+     * Fetch a page of records
+     */
+    pub async fn fetch(&self) -> BasicResponse<TransferPage> {
+        let mut remaining = match self.remaining().await {
+            Ok(remaining) => remaining,
+            Err(error_msg) => {
+                return BasicResponse {
+                    success: false,
+                    error_msg,
+                    data: None,
+                }
+            }
+        };
+
+        let next_cursor =
+            (remaining.len() > self.limit).then(|| remaining[self.limit - 1].0.to_string());
+        remaining.truncate(self.limit);
+
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(TransferPage {
+                records: remaining.into_iter().map(|(_, record)| record).collect(),
+                next_cursor,
+            }),
+        }
+    }
+
+    /**
+     * Stream all the matching records. The records are fetched once and the pages are read from
+     * memory. If the records can't be fetched, the stream has only the error
+     */
+    pub fn stream(self) -> impl Stream<Item = Result<TransferUnitsRegistry, String>> {
+        stream::once(async move { self.remaining().await }).flat_map(|remaining| {
+            let items: Vec<Result<TransferUnitsRegistry, String>> = match remaining {
+                Ok(records) => records.into_iter().map(|(_, record)| Ok(record)).collect(),
+                Err(error_msg) => vec![Err(error_msg)],
+            };
+            stream::iter(items)
+        })
+    }
+}
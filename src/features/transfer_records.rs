@@ -0,0 +1,138 @@
+use crate::{
+    features::{log::AppendLog, structures::TransferUnitsRegistry, units::Units},
+    ChainDB,
+};
+
+// Prefix of the logs holding the transfer records kept by the client for each user
+const TRANSFER_RECORDS_PREFIX: &str = "__transfer_records";
+
+fn records_log(db: &ChainDB, user_id: &str) -> AppendLog<TransferUnitsRegistry> {
+    AppendLog::new(db, &format!("{}:{}", TRANSFER_RECORDS_PREFIX, user_id))
+}
+
+/**
+ * Store the record of a settled transfer for both users, with its memo and transfer id
+ */
+pub async fn record(
+    db: &ChainDB,
+    from: &str,
+    to: &str,
+    units: Units,
+    memo: Option<String>,
+    transfer_id: &str,
+) {
+    // The block information is not known by the client, only the server can give it
+    let record = TransferUnitsRegistry {
+        from: from.to_string(),
+        to: to.to_string(),
+        units,
+        asset: None,
+        transfer_id: Some(transfer_id.to_string()),
+        memo,
+        timestamp: None,
+        block_height: None,
+        block_hash: None,
+    };
+
+    for user_id in [from, to] {
+        let _ = records_log(db, user_id).append(&record).await;
+    }
+}
+
+/**
+ * Fetch the transfer records kept by the client for a user, from the most old to the most
+ * recent. They have the memo and transfer id of each transfer made through this library. The
+ * timestamp and block information are the ones of the stored record, written right after the
+ * transfer was made
+ */
+pub async fn get_records(db: &ChainDB, user_id: &str) -> Vec<TransferUnitsRegistry> {
+    records_log(db, user_id)
+        .read(0..u64::MAX)
+        .await
+        .into_iter()
+        .map(|item| {
+            let mut record = item.item;
+            record.timestamp = item.timestamp;
+            record.block_height = Some(item.block_height);
+            record.block_hash = Some(item.block_hash);
+            record
+        })
+        .collect()
+}
+
+// Records of the same transfer kept by the server and by the client have the same users and
+// amounts
+fn same_transfer(record: &TransferUnitsRegistry, kept: &TransferUnitsRegistry) -> bool {
+    record.from == kept.from
+        && record.to == kept.to
+        && record.units == kept.units
+        && record.asset == kept.asset
+}
+
+// Information sent by the server is never replaced
+fn fill(record: &mut TransferUnitsRegistry, kept: &TransferUnitsRegistry) {
+    record.transfer_id = record
+        .transfer_id
+        .take()
+        .or_else(|| kept.transfer_id.clone());
+    record.memo = record.memo.take().or_else(|| kept.memo.clone());
+    record.timestamp = record.timestamp.or(kept.timestamp);
+    record.block_height = record.block_height.or(kept.block_height);
+    record.block_hash = record.block_hash.take().or_else(|| kept.block_hash.clone());
+}
+
+/**
+ * Add the information kept by the client (memo, transfer id and time) to the whole transfer
+ * history returned by the server, from the most old to the most recent.
+ *
+ * The server returns no transfer id, so records are matched by their users and amounts, in
+ * order. The records of a transfer (users and amounts) are completed only when the server and
+ * the client have the same number of them; otherwise some were made outside this library and
+ * can't be told apart, so they are left as they are
+ */
+pub async fn enrich(db: &ChainDB, user_id: &str, records: &mut [TransferUnitsRegistry]) {
+    if records.is_empty() {
+        return;
+    }
+
+    let kept = get_records(db, user_id).await;
+    let mut matched = vec![false; records.len()];
+
+    for index in 0..records.len() {
+        if matched[index] {
+            continue;
+        }
+
+        let same: Vec<usize> = (index..records.len())
+            .filter(|&other| same_transfer(&records[other], &records[index]))
+            .collect();
+        let same_kept: Vec<&TransferUnitsRegistry> = kept
+            .iter()
+            .filter(|kept| same_transfer(&records[index], kept))
+            .collect();
+
+        for &other in same.iter() {
+            matched[other] = true;
+        }
+        if same.len() != same_kept.len() {
+            continue;
+        }
+
+        for (other, kept) in same.into_iter().zip(same_kept) {
+            fill(&mut records[other], kept);
+        }
+    }
+}
+
+/**
+ * Add the information kept by the client to the last transfer record of a user returned by the
+ * server. It's completed only if the last transfer kept by the client has the same users and
+ * amounts
+ */
+pub async fn enrich_last(db: &ChainDB, user_id: &str, record: &mut TransferUnitsRegistry) {
+    let kept = get_records(db, user_id).await;
+
+    if let Some(last) = kept.last().filter(|last| same_transfer(record, last)) {
+        fill(record, last);
+    }
+}
@@ -0,0 +1,389 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aes_gcm::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    features::{
+        contract::{contract_id, get_last_transaction, post_transaction},
+        recovery::{generate_code, hash_code},
+        session::{register_user_table, user_table_name},
+        structures::{BasicResponse, ContractTransactionData, TransactionType},
+    },
+    ChainDB,
+};
+
+// Name of the two-factor table owned by each user
+const TWO_FACTOR_TABLE: &str = "__two_factor";
+const BACKUP_CODES: usize = 8;
+const SECRET_LENGTH: usize = 20;
+// TOTP parameters used by the authenticator apps (RFC 6238)
+const TIME_STEP: u64 = 30;
+const DIGITS: u32 = 6;
+// Codes of the previous and next time steps are accepted too, to allow some clock drift
+const ALLOWED_DRIFT: u64 = 1;
+// Invalid codes in a row that lock the two-factor verification of a user for
+// `TWO_FACTOR_LOCKOUT` seconds
+const MAX_VERIFY_ATTEMPTS: u32 = 5;
+const TWO_FACTOR_LOCKOUT: u64 = 15 * 60;
+
+/**
+ * Secret to add to an authenticator app. `uri` can be shown as a QR code. Two-factor
+ * authentication is enabled once a code of the app is confirmed
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TwoFactorEnrollment {
+    // Base32, as typed in the authenticator apps
+    pub secret: String,
+    pub uri: String,
+}
+
+/**
+ * Two-factor settings of a user. The TOTP secret is encrypted with a key derived from the
+ * database access key and only hashes of the backup codes are kept
+ */
+#[derive(Debug, Serialize, Deserialize)]
+struct TwoFactorState {
+    enabled: bool,
+    nonce: String,
+    encrypted_secret: String,
+    backup_code_salt: String,
+    backup_code_hashes: Vec<String>,
+    // Time step of the last accepted code, so a code can't be used twice
+    last_used_step: u64,
+    // Invalid codes in a row, kept with the account so new logins don't reset them
+    #[serde(default)]
+    failed_attempts: u32,
+    // Unix timestamp (seconds) until which no code is accepted
+    #[serde(default)]
+    locked_until: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn error<D>(error_msg: String) -> BasicResponse<D> {
+    BasicResponse {
+        success: false,
+        error_msg,
+        data: None,
+    }
+}
+
+fn ok<D>(data: D) -> BasicResponse<D> {
+    BasicResponse {
+        success: true,
+        error_msg: String::new(),
+        data: Some(data),
+    }
+}
+
+fn state_contract_id(db: &ChainDB, user_id: &str) -> String {
+    contract_id(db, &user_table_name(user_id, TWO_FACTOR_TABLE))
+}
+
+// Keyed by the user name, which doesn't change along with the account id on password changes
+fn cipher(db: &ChainDB, user_name: &str) -> Aes256Gcm {
+    let key = Sha256::digest(format!("{}:two_factor:{}", db.access_key, user_name));
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+}
+
+fn decrypt_secret(db: &ChainDB, user_name: &str, state: &TwoFactorState) -> Option<Vec<u8>> {
+    let nonce = hex::decode(&state.nonce).ok()?;
+    let encrypted = hex::decode(&state.encrypted_secret).ok()?;
+
+    if nonce.len() != 12 {
+        return None;
+    }
+
+    cipher(db, user_name)
+        .decrypt(Nonce::from_slice(&nonce), encrypted.as_ref())
+        .ok()
+}
+
+fn hotp(secret: &[u8], counter: u64) -> String {
+    let mut mac = <Hmac<Sha1> as Mac>::new_from_slice(secret).unwrap();
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation (RFC 4226)
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+// Percent-encode a part of the otpauth URI. Only the unreserved characters (RFC 3986) are kept
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/**
+ * TOTP code of a base32 secret at the given unix timestamp (seconds), as shown by the
+ * authenticator apps. Returns `None` if the secret isn't valid base32
+ */
+pub fn totp_code(secret: &str, timestamp: u64) -> Option<String> {
+    let secret = base32::decode(base32::Alphabet::RFC4648 { padding: false }, secret)?;
+    Some(hotp(&secret, timestamp / TIME_STEP))
+}
+
+// Time step of the code, if it's valid now and newer than the last one used
+fn matching_step(secret: &[u8], code: &str, last_used_step: u64) -> Option<u64> {
+    let current = now() / TIME_STEP;
+    let code = code.trim();
+
+    (current.saturating_sub(ALLOWED_DRIFT)..=current + ALLOWED_DRIFT)
+        .filter(|step| *step > last_used_step)
+        .find(|step| hotp(secret, *step) == code)
+}
+
+// Two-factor settings of a user. `None` if the user never enrolled
+async fn get_state(db: &ChainDB, user_id: &str) -> Option<TwoFactorState> {
+    let contract_data: Option<ContractTransactionData<TwoFactorState>> =
+        get_last_transaction(db, &state_contract_id(db, user_id)).await;
+
+    contract_data
+        .filter(|data| data.tx_type == TransactionType::CONTRACT)
+        .map(|data| data.data)
+}
+
+/**
+ * Whether a user has two-factor authentication enabled
+ */
+pub(crate) async fn is_enabled(db: &ChainDB, user_id: &str) -> bool {
+    get_state(db, user_id)
+        .await
+        .is_some_and(|state| state.enabled)
+}
+
+async fn set_state(db: &ChainDB, user_id: &str, state: &TwoFactorState) -> BasicResponse<String> {
+    // Registered as a user table, so it moves along with the account on password changes
+    let registered = register_user_table(db, user_id, TWO_FACTOR_TABLE).await;
+    if !registered.success {
+        return registered;
+    }
+
+    post_transaction(db, &state_contract_id(db, user_id), state).await
+}
+
+/**
+ * Generate a new TOTP secret for a user. It replaces any previous enrollment not confirmed yet
+ */
+pub(crate) async fn enroll(
+    db: &ChainDB,
+    user_id: &str,
+    user_name: &str,
+    issuer: &str,
+) -> BasicResponse<TwoFactorEnrollment> {
+    if is_enabled(db, user_id).await {
+        return error("Two-factor authentication is already enabled".to_string());
+    }
+
+    let mut secret = [0u8; SECRET_LENGTH];
+    OsRng.fill_bytes(&mut secret);
+
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let encrypted = cipher(db, user_name)
+        .encrypt(&nonce, secret.as_ref())
+        .unwrap();
+
+    let state = TwoFactorState {
+        enabled: false,
+        nonce: hex::encode(nonce),
+        encrypted_secret: hex::encode(encrypted),
+        backup_code_salt: String::new(),
+        backup_code_hashes: vec![],
+        last_used_step: 0,
+        failed_attempts: 0,
+        locked_until: 0,
+    };
+
+    let stored = set_state(db, user_id, &state).await;
+    if !stored.success {
+        return error(stored.error_msg);
+    }
+
+    let secret = base32::encode(base32::Alphabet::RFC4648 { padding: false }, &secret);
+    let uri = format!(
+        "otpauth://totp/{issuer}:{user_name}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={TIME_STEP}",
+        issuer = percent_encode(issuer),
+        user_name = percent_encode(user_name),
+        secret = secret,
+    );
+
+    ok(TwoFactorEnrollment { secret, uri })
+}
+
+/**
+ * Enable two-factor authentication with a code of the enrolled secret. Returns the backup
+ * codes, each one can be used once instead of a TOTP code
+ */
+pub(crate) async fn confirm(
+    db: &ChainDB,
+    user_id: &str,
+    user_name: &str,
+    code: &str,
+) -> BasicResponse<Vec<String>> {
+    let Some(mut state) = get_state(db, user_id).await else {
+        return error("Two-factor authentication was not enrolled".to_string());
+    };
+    if state.enabled {
+        return error("Two-factor authentication is already enabled".to_string());
+    }
+
+    let Some(secret) = decrypt_secret(db, user_name, &state) else {
+        return error("Two-factor data of the user could not be read".to_string());
+    };
+    let Some(step) = matching_step(&secret, code, state.last_used_step) else {
+        return error("Invalid two-factor code".to_string());
+    };
+
+    let codes: Vec<String> = (0..BACKUP_CODES).map(|_| generate_code()).collect();
+    let salt = random_string::generate(16, "abcdefghijklmnopqrstuvwxyz0123456789");
+
+    state.enabled = true;
+    state.last_used_step = step;
+    state.backup_code_hashes = codes.iter().map(|code| hash_code(&salt, code)).collect();
+    state.backup_code_salt = salt;
+
+    let stored = set_state(db, user_id, &state).await;
+    if !stored.success {
+        return error(stored.error_msg);
+    }
+
+    ok(codes)
+}
+
+/**
+ * Check a TOTP code or a backup code of a user with two-factor authentication enabled. Used
+ * codes are not accepted again. After `MAX_VERIFY_ATTEMPTS` invalid codes in a row, the
+ * verification of the user is locked for a while, whatever session the codes come from
+ */
+pub(crate) async fn verify(
+    db: &ChainDB,
+    user_id: &str,
+    user_name: &str,
+    code: &str,
+) -> Result<(), String> {
+    // Attempts of this process are counted one at a time
+    let _guard = db.locks.lock(&format!("two_factor:{}", user_id)).await;
+
+    let invalid = "Invalid two-factor code".to_string();
+    let Some(mut state) = get_state(db, user_id).await.filter(|s| s.enabled) else {
+        return Err("Two-factor authentication is not enabled".to_string());
+    };
+
+    let now = now();
+    if state.locked_until > now {
+        return Err(locked(state.locked_until - now));
+    }
+
+    let Some(secret) = decrypt_secret(db, user_name, &state) else {
+        return Err("Two-factor data of the user could not be read".to_string());
+    };
+
+    if let Some(step) = matching_step(&secret, code, state.last_used_step) {
+        state.last_used_step = step;
+    } else {
+        let hash = hash_code(&state.backup_code_salt, code);
+        if !state.backup_code_hashes.contains(&hash) {
+            state.failed_attempts += 1;
+            let mut error_msg = invalid;
+            if state.failed_attempts >= MAX_VERIFY_ATTEMPTS {
+                state.failed_attempts = 0;
+                state.locked_until = now + TWO_FACTOR_LOCKOUT;
+                error_msg = locked(TWO_FACTOR_LOCKOUT);
+            }
+
+            let stored = set_state(db, user_id, &state).await;
+            if !stored.success {
+                return Err(format!(
+                    "{}. The failed attempt could not be recorded: {}",
+                    error_msg, stored.error_msg
+                ));
+            }
+            return Err(error_msg);
+        }
+        state
+            .backup_code_hashes
+            .retain(|code_hash| code_hash != &hash);
+    }
+
+    state.failed_attempts = 0;
+    let stored = set_state(db, user_id, &state).await;
+    if !stored.success {
+        return Err(stored.error_msg);
+    }
+
+    Ok(())
+}
+
+fn locked(seconds: u64) -> String {
+    format!(
+        "Too many invalid two-factor codes, try again in {} seconds",
+        seconds
+    )
+}
+
+/**
+ * Whether the two-factor verification of a user is locked after too many invalid codes
+ */
+pub(crate) async fn is_locked(db: &ChainDB, user_id: &str) -> bool {
+    get_state(db, user_id)
+        .await
+        .is_some_and(|state| state.locked_until > now())
+}
+
+/**
+ * Disable two-factor authentication of a user, after checking one of its codes
+ */
+pub(crate) async fn disable(
+    db: &ChainDB,
+    user_id: &str,
+    user_name: &str,
+    code: &str,
+) -> BasicResponse<String> {
+    if let Err(error_msg) = verify(db, user_id, user_name, code).await {
+        return error(error_msg);
+    }
+
+    let state = TwoFactorState {
+        enabled: false,
+        nonce: String::new(),
+        encrypted_secret: String::new(),
+        backup_code_salt: String::new(),
+        backup_code_hashes: vec![],
+        last_used_step: 0,
+        failed_attempts: 0,
+        locked_until: 0,
+    };
+
+    set_state(db, user_id, &state).await
+}
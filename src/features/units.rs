@@ -0,0 +1,147 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/**
+ * Amount of units. It's stored as an integer amount of the smallest fraction, e.g. cents, and
+ * is sent to the server as a plain integer
+ */
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Units(u64);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnitsError {
+    Empty,
+    InvalidDigit,
+    TooManyDecimals { max: u32 },
+    Overflow,
+}
+
+impl fmt::Display for UnitsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnitsError::Empty => write!(f, "Units value is empty"),
+            UnitsError::InvalidDigit => write!(f, "Units value has an invalid digit"),
+            UnitsError::TooManyDecimals { max } => {
+                write!(f, "Units value has more than {} decimal places", max)
+            }
+            UnitsError::Overflow => write!(f, "Units value is too large"),
+        }
+    }
+}
+
+impl std::error::Error for UnitsError {}
+
+impl Units {
+    pub const ZERO: Units = Units(0);
+
+    pub const fn new(amount: u64) -> Self {
+        Self(amount)
+    }
+
+    /**
+     * Integer amount, as stored on chain
+     */
+    pub const fn amount(self) -> u64 {
+        self.0
+    }
+
+    pub fn checked_add(self, other: Units) -> Option<Units> {
+        self.0.checked_add(other.0).map(Units)
+    }
+
+    pub fn checked_sub(self, other: Units) -> Option<Units> {
+        self.0.checked_sub(other.0).map(Units)
+    }
+
+    pub fn checked_mul(self, factor: u64) -> Option<Units> {
+        self.0.checked_mul(factor).map(Units)
+    }
+
+    /**
+     * Parse a decimal value using the given precision, e.g. "12.34" with 2 decimals is 1234 units
+     */
+    pub fn from_decimal(value: &str, decimals: u32) -> Result<Units, UnitsError> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(UnitsError::Empty);
+        }
+
+        let (integer, fraction) = value.split_once('.').unwrap_or((value, ""));
+        if integer.is_empty() && fraction.is_empty() {
+            return Err(UnitsError::InvalidDigit);
+        }
+        if fraction.len() as u32 > decimals {
+            return Err(UnitsError::TooManyDecimals { max: decimals });
+        }
+
+        let scale = 10u64.checked_pow(decimals).ok_or(UnitsError::Overflow)?;
+        let integer = parse_digits(integer)?;
+        let fraction_scale = 10u64.pow(decimals - fraction.len() as u32);
+        let fraction = parse_digits(fraction)?;
+
+        integer
+            .checked_mul(scale)
+            .and_then(|amount| amount.checked_add(fraction * fraction_scale))
+            .map(Units)
+            .ok_or(UnitsError::Overflow)
+    }
+
+    /**
+     * Format as a decimal value using the given precision, e.g. 1234 units with 2 decimals is "12.34"
+     */
+    pub fn to_decimal(self, decimals: u32) -> String {
+        if decimals == 0 {
+            return self.0.to_string();
+        }
+
+        let digits = format!("{:0>width$}", self.0, width = decimals as usize + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - decimals as usize);
+        format!("{}.{}", integer, fraction)
+    }
+}
+
+fn parse_digits(digits: &str) -> Result<u64, UnitsError> {
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(UnitsError::InvalidDigit);
+    }
+    digits.parse::<u64>().map_err(|_| UnitsError::Overflow)
+}
+
+impl From<u64> for Units {
+    fn from(amount: u64) -> Self {
+        Self(amount)
+    }
+}
+
+impl From<Units> for u64 {
+    fn from(units: Units) -> Self {
+        units.0
+    }
+}
+
+impl PartialEq<u64> for Units {
+    fn eq(&self, other: &u64) -> bool {
+        self.0 == *other
+    }
+}
+
+impl fmt::Display for Units {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for Units {
+    type Err = UnitsError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Units::from_decimal(value, 0)
+    }
+}
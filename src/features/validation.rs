@@ -0,0 +1,396 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::{skeleton, MixedScript};
+
+// Passwords rejected regardless of their length and characters
+const COMMON_PASSWORDS: [&str; 16] = [
+    "password",
+    "passw0rd",
+    "123456",
+    "1234567890",
+    "12345678",
+    "123456789",
+    "qwerty",
+    "qwertyuiop",
+    "abc123",
+    "111111",
+    "letmein",
+    "iloveyou",
+    "admin",
+    "welcome",
+    "monkey",
+    "football",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum PasswordStrength {
+    VeryWeak,
+    Weak,
+    Fair,
+    Strong,
+    VeryStrong,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum UserNameCharset {
+    // ASCII letters and digits
+    Ascii,
+    // Letters and digits of any script
+    Unicode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Field {
+    UserName,
+    Password,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ValidationError {
+    Empty {
+        field: Field,
+    },
+    TooShort {
+        field: Field,
+        min: usize,
+        length: usize,
+    },
+    TooLong {
+        field: Field,
+        max: usize,
+        length: usize,
+    },
+    ControlCharacter {
+        field: Field,
+        position: usize,
+    },
+    InvalidCharacter {
+        field: Field,
+        character: char,
+        position: usize,
+    },
+    // The user name mixes scripts, e.g. latin and cyrillic letters
+    MixedScripts,
+    // The user name looks like an ASCII user name, e.g. "pаypal" with a cyrillic "а"
+    Confusable {
+        skeleton: String,
+    },
+    WeakPassword {
+        strength: PasswordStrength,
+        min_strength: PasswordStrength,
+    },
+    PasswordContainsUserName,
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Field::UserName => write!(f, "User name"),
+            Field::Password => write!(f, "Password"),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Empty { field } => write!(f, "{} is empty", field),
+            ValidationError::TooShort { field, min, length } => write!(
+                f,
+                "{} has {} characters, the minimum is {}",
+                field, length, min
+            ),
+            ValidationError::TooLong { field, max, length } => write!(
+                f,
+                "{} has {} characters, the maximum is {}",
+                field, length, max
+            ),
+            ValidationError::ControlCharacter { field, position } => write!(
+                f,
+                "{} has a control character at position {}",
+                field, position
+            ),
+            ValidationError::InvalidCharacter {
+                field,
+                character,
+                position,
+            } => write!(
+                f,
+                "{} has an invalid character {:?} at position {}",
+                field, character, position
+            ),
+            ValidationError::MixedScripts => {
+                write!(f, "User name mixes characters of different scripts")
+            }
+            ValidationError::Confusable { skeleton } => {
+                write!(f, "User name can be confused with \"{}\"", skeleton)
+            }
+            ValidationError::WeakPassword {
+                strength,
+                min_strength,
+            } => write!(
+                f,
+                "Password is too weak ({:?}), the minimum strength is {:?}",
+                strength, min_strength
+            ),
+            ValidationError::PasswordContainsUserName => {
+                write!(f, "Password contains the user name")
+            }
+        }
+    }
+}
+
+/**
+ * Validation errors of a user name and/or password
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValidationErrors(pub Vec<ValidationError>);
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errors: Vec<String> = self.0.iter().map(|error| error.to_string()).collect();
+        write!(f, "{}", errors.join("; "))
+    }
+}
+
+/**
+ * Normalize a user name (Unicode NFC), so the same name typed in different ways is the same
+ * account
+ */
+pub fn normalize_user_name(user_name: &str) -> String {
+    user_name.nfc().collect()
+}
+
+/**
+ * Score a password by its length and the kinds of characters it uses. Common passwords and
+ * passwords with few distinct characters are very weak
+ */
+pub fn password_strength(password: &str, user_name: Option<&str>) -> PasswordStrength {
+    let length = password.chars().count();
+    let lowercase = password.to_lowercase();
+
+    let mut distinct: Vec<char> = password.chars().collect();
+    distinct.sort();
+    distinct.dedup();
+
+    if COMMON_PASSWORDS.contains(&lowercase.as_str()) || distinct.len() < 4 {
+        return PasswordStrength::VeryWeak;
+    }
+
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_numeric()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ]
+    .iter()
+    .filter(|class| **class)
+    .count();
+
+    let score = [
+        length >= 8,
+        length >= 12,
+        length >= 16,
+        classes >= 2,
+        classes >= 3,
+    ]
+    .iter()
+    .filter(|rule| **rule)
+    .count();
+
+    let strength = match score {
+        0 => PasswordStrength::VeryWeak,
+        1 => PasswordStrength::Weak,
+        2 => PasswordStrength::Fair,
+        3 => PasswordStrength::Strong,
+        _ => PasswordStrength::VeryStrong,
+    };
+
+    match user_name {
+        Some(user_name) if contains_user_name(&lowercase, user_name) => {
+            strength.min(PasswordStrength::Weak)
+        }
+        _ => strength,
+    }
+}
+
+fn contains_user_name(lowercase_password: &str, user_name: &str) -> bool {
+    let user_name = user_name.to_lowercase();
+    user_name.chars().count() >= 3 && lowercase_password.contains(&user_name)
+}
+
+/**
+ * Rules checked before user names and passwords are sent to the server
+ */
+#[derive(Debug, Clone)]
+pub struct ValidationRules {
+    pub user_name_min_length: usize,
+    pub user_name_max_length: usize,
+    pub user_name_charset: UserNameCharset,
+    // Characters allowed in user names besides letters and digits
+    pub user_name_symbols: String,
+    pub reject_confusable_user_names: bool,
+    pub password_min_length: usize,
+    pub password_max_length: usize,
+    pub password_min_strength: PasswordStrength,
+}
+
+impl Default for ValidationRules {
+    fn default() -> Self {
+        Self {
+            user_name_min_length: 3,
+            user_name_max_length: 32,
+            user_name_charset: UserNameCharset::Unicode,
+            user_name_symbols: "._-".to_string(),
+            reject_confusable_user_names: true,
+            password_min_length: 8,
+            password_max_length: 128,
+            password_min_strength: PasswordStrength::Fair,
+        }
+    }
+}
+
+fn check_length(
+    errors: &mut Vec<ValidationError>,
+    field: Field,
+    length: usize,
+    min: usize,
+    max: usize,
+) {
+    if length == 0 {
+        errors.push(ValidationError::Empty { field });
+    } else if length < min {
+        errors.push(ValidationError::TooShort { field, min, length });
+    } else if length > max {
+        errors.push(ValidationError::TooLong { field, max, length });
+    }
+}
+
+impl ValidationRules {
+    /**
+     * Validate a user name. Returns it normalized (Unicode NFC), as new accounts are created.
+     * Existing accounts may have a name that is not normalized (see `ChainDB::get_user_account`)
+     */
+    pub fn validate_user_name(&self, user_name: &str) -> Result<String, ValidationErrors> {
+        let user_name = normalize_user_name(user_name);
+        let mut errors = vec![];
+
+        check_length(
+            &mut errors,
+            Field::UserName,
+            user_name.chars().count(),
+            self.user_name_min_length,
+            self.user_name_max_length,
+        );
+
+        for (position, character) in user_name.chars().enumerate() {
+            if character.is_control() {
+                errors.push(ValidationError::ControlCharacter {
+                    field: Field::UserName,
+                    position,
+                });
+                continue;
+            }
+
+            let allowed = match self.user_name_charset {
+                UserNameCharset::Ascii => character.is_ascii_alphanumeric(),
+                UserNameCharset::Unicode => character.is_alphanumeric(),
+            } || self.user_name_symbols.contains(character);
+
+            if !allowed {
+                errors.push(ValidationError::InvalidCharacter {
+                    field: Field::UserName,
+                    character,
+                    position,
+                });
+            }
+        }
+
+        if self.reject_confusable_user_names && !user_name.is_ascii() {
+            if !user_name.as_str().is_single_script() {
+                errors.push(ValidationError::MixedScripts);
+            }
+
+            let skeleton: String = skeleton(&user_name).collect();
+            if skeleton.is_ascii() {
+                errors.push(ValidationError::Confusable { skeleton });
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(user_name)
+        } else {
+            Err(ValidationErrors(errors))
+        }
+    }
+
+    /**
+     * Validate a password, checking its strength
+     */
+    pub fn validate_password(
+        &self,
+        password: &str,
+        user_name: Option<&str>,
+    ) -> Result<(), ValidationErrors> {
+        let mut errors = vec![];
+
+        check_length(
+            &mut errors,
+            Field::Password,
+            password.chars().count(),
+            self.password_min_length,
+            self.password_max_length,
+        );
+
+        if let Some(position) = password.chars().position(|c| c.is_control()) {
+            errors.push(ValidationError::ControlCharacter {
+                field: Field::Password,
+                position,
+            });
+        }
+
+        if let Some(user_name) = user_name {
+            if contains_user_name(&password.to_lowercase(), user_name) {
+                errors.push(ValidationError::PasswordContainsUserName);
+            }
+        }
+
+        let strength = password_strength(password, user_name);
+        if !password.is_empty() && strength < self.password_min_strength {
+            errors.push(ValidationError::WeakPassword {
+                strength,
+                min_strength: self.password_min_strength,
+            });
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(errors))
+        }
+    }
+
+    /**
+     * Validate the user name and password of a new account. Returns the normalized user name
+     */
+    pub fn validate_account(
+        &self,
+        user_name: &str,
+        password: &str,
+    ) -> Result<String, ValidationErrors> {
+        let user_name = self.validate_user_name(user_name);
+        let normalized = user_name.as_ref().ok().map(|name| name.as_str());
+        let password = self.validate_password(password, normalized);
+
+        match (user_name, password) {
+            (Ok(user_name), Ok(())) => Ok(user_name),
+            (user_name, password) => {
+                let mut errors = user_name.err().map(|e| e.0).unwrap_or_default();
+                errors.extend(password.err().map(|e| e.0).unwrap_or_default());
+                Err(ValidationErrors(errors))
+            }
+        }
+    }
+}
@@ -1,18 +1,64 @@
 use features::{
+    aggregate::AggregateRepository,
+    assets,
+    batch::{self, BatchTransferReport},
+    credentials,
+    escrow::Escrow,
+    idempotency,
+    transfer_query::TransferQuery,
+    transfer_records,
     constants::{
         API, CREATE_USER_ACCOUNT, GET_ALL_TRANSFER_BY_USER_ID, GET_TRANSFER_BY_USER_ID,
         GET_USER_ACCOUNT, GET_USER_ACCOUNT_BY_ID, TRANSFER_UNITS, CHECK_USER_NAME,
     },
+    kv::KeyValueStore,
+    locks::KeyedLocks,
+    log::AppendLog,
+    multisig::MultiSig,
+    policy,
+    profile,
+    reconciliation::{self, ReconciliationReport},
+    recovery::{self, RecoverableAccount},
+    scheduler::TransferScheduler,
+    session::DEFAULT_SESSION_DURATION,
+    statement::{self, AccountStatement},
     structures::{Access, BasicResponse, SignedUserAccount, TransferUnitsRegistry},
     table::Table,
+    transaction::{self, TableTransaction},
+    token,
+    transfer_operation::{self, TransferOperation},
+    two_factor,
+    validation::normalize_user_name,
 };
 use reqwest::header::CONTENT_TYPE;
 use serde::{de::DeserializeOwned, Serialize};
 use serde_json::json;
+use std::{collections::BTreeMap, ops::RangeBounds, sync::Arc, time::Duration};
 
 mod features;
 mod test;
 
+pub use features::aggregate::{Aggregate, VersionedAggregate};
+pub use features::batch::{BatchOptions, Transfer};
+pub use features::escrow::HoldStatus;
+pub use features::multisig::{ProposalAction, ProposalStatus};
+pub use features::policy::{
+    AllowList, DailyLimit, DenyList, MaxTransferAmount, OnlyFor, TransferContext, TransferPolicy,
+    Velocity,
+};
+pub use features::profile::UserProfile;
+pub use features::reconciliation::AuditAccount;
+pub use features::session::UserSession;
+pub use features::throttle::{LoginThrottle, ThrottleOptions};
+pub use features::token::SessionClaims;
+pub use features::transfer_query::{SortOrder, TransferDirection};
+pub use features::two_factor::{totp_code, TwoFactorEnrollment};
+pub use features::units::{Units, UnitsError};
+pub use features::validation::{
+    password_strength, Field, PasswordStrength, UserNameCharset, ValidationError,
+    ValidationErrors, ValidationRules,
+};
+
 // ChainDB features
 #[derive(Clone, Debug)]
 pub struct ChainDB {
@@ -20,6 +66,14 @@ pub struct ChainDB {
     pub name: String,
     pub access: Access,
     pub access_key: String,
+    // Shared by all the requests, so connections are reused
+    client: reqwest::Client,
+    // Evaluated in order before each transfer
+    transfer_policies: Vec<Arc<dyn TransferPolicy>>,
+    // Checked before user names and passwords are sent to the server
+    validation_rules: ValidationRules,
+    // Serialize the changes made through this handle and its clones
+    locks: KeyedLocks,
 }
 
 impl ChainDB {
@@ -45,6 +99,43 @@ impl ChainDB {
             },
             // DB Access Key (used to encrypt its data)
             access_key: key,
+            client: reqwest::Client::new(),
+            transfer_policies: vec![],
+            validation_rules: ValidationRules::default(),
+            locks: KeyedLocks::default(),
+        }
+    }
+
+    /**
+     * Add a policy evaluated before each transfer, e.g. a daily limit. Policies are evaluated
+     * in the order they were added and the first rejection stops the transfer
+     */
+    pub fn with_transfer_policy(mut self, policy: impl TransferPolicy + 'static) -> Self {
+        self.transfer_policies.push(Arc::new(policy));
+        self
+    }
+
+    /**
+     * Replace the rules checked before user names and passwords are sent to the server
+     */
+    pub fn with_validation_rules(mut self, rules: ValidationRules) -> Self {
+        self.validation_rules = rules;
+        self
+    }
+
+    /**
+     * Rules checked before user names and passwords are sent to the server. Use them to
+     * validate a form and get structured errors
+     */
+    pub fn validation_rules(&self) -> &ValidationRules {
+        &self.validation_rules
+    }
+
+    // Used to transfer units back (compensations), which must not be stopped by the policies
+    pub(crate) fn without_transfer_policies(&self) -> Self {
+        Self {
+            transfer_policies: vec![],
+            ..self.clone()
         }
     }
 
@@ -55,7 +146,39 @@ impl ChainDB {
         &self,
         user_name: &str,
         password: &str,
-        units: Option<u64>,
+        units: Option<Units>,
+        password_hint: Option<String>,
+    ) -> BasicResponse<SignedUserAccount> {
+        let user_name = match self.validation_rules.validate_account(user_name, password) {
+            Ok(user_name) => user_name,
+            Err(errors) => {
+                return BasicResponse {
+                    success: false,
+                    error_msg: errors.to_string(),
+                    data: None,
+                }
+            }
+        };
+        let user_name = user_name.as_str();
+
+        let account = self
+            .post_user_account(user_name, password, units, password_hint.clone())
+            .await;
+
+        // The server doesn't return the hint, so it's kept to be read back
+        if let (true, Some(hint)) = (account.success, password_hint.as_ref()) {
+            recovery::store_password_hint(self, user_name, hint).await;
+        }
+
+        account
+    }
+
+    // Create the account on the server, without checking the name and password
+    pub(crate) async fn post_user_account(
+        &self,
+        user_name: &str,
+        password: &str,
+        units: Option<Units>,
         password_hint: Option<String>,
     ) -> BasicResponse<SignedUserAccount> {
         let body = json!({
@@ -70,8 +193,8 @@ impl ChainDB {
 
         let url = format!("{api}{route}", api = self.api, route = CREATE_USER_ACCOUNT);
 
-        let client = reqwest::Client::new();
-        let response = client
+        let response = self
+            .client
             .post(url)
             .header(CONTENT_TYPE, "application/json")
             .body(json_body)
@@ -86,12 +209,155 @@ impl ChainDB {
     }
 
     /**
-     * Get user account info (login method)
+     * Create a new user account along with its recovery codes, which can be used to reset the
+     * password if it's forgotten
+     */
+    pub async fn create_user_account_with_recovery(
+        &self,
+        user_name: &str,
+        password: &str,
+        units: Option<Units>,
+        password_hint: Option<String>,
+    ) -> BasicResponse<RecoverableAccount> {
+        let account = self
+            .create_user_account(user_name, password, units, password_hint)
+            .await;
+        let Some(account) = account.data else {
+            return BasicResponse {
+                success: false,
+                error_msg: account.error_msg,
+                data: None,
+            };
+        };
+
+        let codes = recovery::create_recovery_codes(self, user_name, password).await;
+        let Some(recovery_codes) = codes.data else {
+            return BasicResponse {
+                success: false,
+                error_msg: codes.error_msg,
+                data: None,
+            };
+        };
+
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(RecoverableAccount {
+                account,
+                recovery_codes,
+            }),
+        }
+    }
+
+    /**
+     * Get the password hint given on the account creation
+     */
+    pub async fn get_password_hint(&self, user_name: &str) -> BasicResponse<String> {
+        recovery::get_password_hint(self, user_name).await
+    }
+
+    /**
+     * Generate new recovery codes for a user, replacing the previous ones
+     */
+    pub async fn create_recovery_codes(
+        &self,
+        user_name: &str,
+        password: &str,
+    ) -> BasicResponse<Vec<String>> {
+        recovery::create_recovery_codes(self, user_name, password).await
+    }
+
+    /**
+     * Reset the password of a user using one of its recovery codes. Each code can be used once
+     */
+    pub async fn recover_account(
+        &self,
+        user_name: &str,
+        recovery_code: &str,
+        new_password: &str,
+    ) -> BasicResponse<SignedUserAccount> {
+        recovery::recover_account(self, user_name, recovery_code, new_password).await
+    }
+
+    /**
+     * Create a new user account with initial balances of named assets, e.g. gold and gems.
+     * Accounts can't be deleted, so if some assets can't be granted the response is not a
+     * success, but the created account is still returned in `data` and the assets not granted
+     * are listed in the error message
+     */
+    pub async fn create_user_account_with_assets(
+        &self,
+        user_name: &str,
+        password: &str,
+        units: Option<Units>,
+        password_hint: Option<String>,
+        assets: BTreeMap<String, Units>,
+    ) -> BasicResponse<SignedUserAccount> {
+        let account = self
+            .create_user_account(user_name, password, units, password_hint)
+            .await;
+
+        let Some(user) = &account.data else {
+            return account;
+        };
+
+        let mut not_granted = vec![];
+        for (asset, asset_units) in assets {
+            let granted = self.grant_asset(&user.id, &asset, asset_units).await;
+            if !granted.success {
+                not_granted.push(format!("{} ({})", asset, granted.error_msg));
+            }
+        }
+
+        if not_granted.is_empty() {
+            return account;
+        }
+
+        BasicResponse {
+            success: false,
+            error_msg: format!(
+                "Account was created, but these assets could not be granted: {}",
+                not_granted.join(", ")
+            ),
+            data: account.data,
+        }
+    }
+
+    /**
+     * Get user account info (login method). The name is normalized (Unicode NFC) first. Accounts
+     * created before user names were normalized keep the name as it was typed, so it's tried
+     * as given if the normalized name doesn't sign in.
+     *
+     * After a password change the account has another name on the server (see
+     * `change_password`), which is used instead
      */
     pub async fn get_user_account(
         &self,
         user_name: &str,
         password: &str,
+    ) -> BasicResponse<SignedUserAccount> {
+        if let Some(name) = credentials::account_name(self, user_name).await {
+            let mut account = self.fetch_user_account(&name.server_name, password).await;
+            if let Some(account) = account.data.as_mut() {
+                account.user_name = name.user_name;
+            }
+            return account;
+        }
+
+        let normalized = normalize_user_name(user_name);
+        let account = self.fetch_user_account(&normalized, password).await;
+
+        if !account.success && normalized != user_name {
+            return self.fetch_user_account(user_name, password).await;
+        }
+
+        account
+    }
+
+    pub(crate) async fn fetch_user_account(
+        &self,
+        user_name: &str,
+        password: &str,
     ) -> BasicResponse<SignedUserAccount> {
         let url = format!(
             "{api}{route}/{user_name}/{user_pass}/{db_access_key}",
@@ -107,6 +373,79 @@ impl ChainDB {
         serde_json::from_str::<BasicResponse<SignedUserAccount>>(&res_json).unwrap()
     }
 
+    /**
+     * Change the password of a user, verifying the current one first. A new account is created
+     * with the new password, the units and the tables owned by the user are moved to it and the
+     * old account is retired
+     */
+    pub async fn change_password(
+        &self,
+        user_name: &str,
+        password: &str,
+        new_password: &str,
+    ) -> BasicResponse<SignedUserAccount> {
+        credentials::change_password(self, user_name, password, new_password).await
+    }
+
+    /**
+     * Sign in a user, returning a session that expires in one hour
+     */
+    pub async fn login(&self, user_name: &str, password: &str) -> BasicResponse<UserSession> {
+        self.login_for(user_name, password, DEFAULT_SESSION_DURATION).await
+    }
+
+    /**
+     * Sign in a user, returning a session that expires after the given duration
+     */
+    pub async fn login_for(
+        &self,
+        user_name: &str,
+        password: &str,
+        duration: Duration,
+    ) -> BasicResponse<UserSession> {
+        let account = self.get_user_account(user_name, password).await;
+        let Some(account) = account.data else {
+            return BasicResponse {
+                success: false,
+                error_msg: account.error_msg,
+                data: None,
+            };
+        };
+
+        // Users with two-factor authentication complete the login with `verify_two_factor`
+        let two_factor_pending = two_factor::is_enabled(self, &account.id).await;
+
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(UserSession::new(self, account, duration, two_factor_pending)),
+        }
+    }
+
+    /**
+     * Check the signature and expiry of a session token (see `UserSession::issue_token`),
+     * returning its claims. The account id of the user is `sub`
+     */
+    pub fn verify_session_token(&self, token: &str) -> BasicResponse<SessionClaims> {
+        token::verify(self, token)
+    }
+
+    /**
+     * Get the session of a token, so a request can act on behalf of its user without the
+     * password. The session expires along with the token
+     */
+    pub async fn resume_session(&self, token: &str) -> BasicResponse<UserSession> {
+        token::resume_session(self, token).await
+    }
+
+    /**
+     * Get a login throttle. Its logins are rejected for a while after failed attempts of the
+     * same user name or source
+     */
+    pub fn login_throttle(&self, options: ThrottleOptions) -> LoginThrottle {
+        LoginThrottle::new(self, options)
+    }
+
     /**
      * Get user account info by its id
      */
@@ -128,9 +467,44 @@ impl ChainDB {
     }
 
     /**
-     * Check if user_name is already taken
+     * Get the profile of a user by its account id. Profiles are written by the session of the
+     * user (see `UserSession::set_profile`)
+     */
+    pub async fn get_profile<Profile: DeserializeOwned>(
+        &self,
+        user_id: &str,
+    ) -> BasicResponse<Profile> {
+        profile::get_profile(self, user_id).await
+    }
+
+    /**
+     * Check if user_name is already taken. Both the normalized name (Unicode NFC) and the name as
+     * given are checked, since accounts created before user names were normalized keep the name
+     * as it was typed
      */
     pub async fn check_user_name(&self, user_name: &str) -> BasicResponse<String> {
+        let normalized = match self.validation_rules.validate_user_name(user_name) {
+            Ok(user_name) => user_name,
+            Err(errors) => {
+                return BasicResponse {
+                    success: false,
+                    error_msg: errors.to_string(),
+                    data: None,
+                }
+            }
+        };
+
+        if normalized != user_name {
+            let taken = self.fetch_user_name(user_name).await;
+            if taken.success {
+                return taken;
+            }
+        }
+
+        self.fetch_user_name(&normalized).await
+    }
+
+    async fn fetch_user_name(&self, user_name: &str) -> BasicResponse<String> {
         let url = format!(
             "{api}{route}/{user_name}/{db_access_key}",
             api = self.api,
@@ -147,12 +521,111 @@ impl ChainDB {
     /**
      * Transfer units between users
      */
-    pub async fn transfer_units(
+    pub async fn transfer_units(&self, from: &str, to: &str, units: Units) -> BasicResponse<String> {
+        self.send_transfer(from, to, units, None, None).await
+    }
+
+    /**
+     * Transfer units between users with a memo (reason or reference), kept in the transfer
+     * records of the client (see `get_transfer_records_by_user_id`)
+     */
+    pub async fn transfer_units_with_memo(
+        &self,
+        from: &str,
+        to: &str,
+        units: Units,
+        memo: Option<String>,
+    ) -> BasicResponse<String> {
+        self.send_transfer(from, to, units, memo, None).await
+    }
+
+    /**
+     * Transfer units between users using a client generated transfer id. Retrying a transfer
+     * with the same id doesn't send it again once it was sent.
+     *
+     * The server ignores transfer ids: the id is claimed on the client before sending, so
+     * calls of other processes using the same id at the same moment are not prevented. If the
+     * outcome of the transfer is unknown (e.g. a timeout), it has to be recorded with
+     * `resolve_transfer`
+     */
+    pub async fn transfer_units_with_id(
+        &self,
+        transfer_id: &str,
+        from: &str,
+        to: &str,
+        units: Units,
+        memo: Option<String>,
+    ) -> BasicResponse<String> {
+        idempotency::transfer_with_id(self, transfer_id, from, to, units, memo).await
+    }
+
+    /**
+     * Record whether a transfer made with an id whose outcome is unknown was made. If it
+     * wasn't, it can be sent again with the same id
+     */
+    pub async fn resolve_transfer(&self, transfer_id: &str, settled: bool) -> BasicResponse<String> {
+        idempotency::resolve_transfer(self, transfer_id, settled).await
+    }
+
+    /**
+     * Fetch the Transference of units Record made with the given transfer id, if it was settled
+     */
+    pub async fn get_transfer_by_transfer_id(
+        &self,
+        transfer_id: &str,
+    ) -> BasicResponse<TransferUnitsRegistry> {
+        idempotency::get_settled_transfer(self, transfer_id).await
+    }
+
+    pub(crate) async fn send_transfer(
         &self,
         from: &str,
         to: &str,
-        units: u64,
+        units: Units,
+        memo: Option<String>,
+        transfer_id: Option<&str>,
     ) -> BasicResponse<String> {
+        match self.post_transfer(from, to, units, memo, transfer_id).await {
+            Ok(response) => response,
+            Err(error_msg) => BasicResponse {
+                success: false,
+                error_msg,
+                data: None,
+            },
+        }
+    }
+
+    /**
+     * Send a transfer to the server. `Err` means its outcome is unknown (e.g. the request
+     * timed out), the transfer may or may not have been made.
+     *
+     * The server doesn't keep transfer ids, so they are only stored in the client side
+     * records (see `transfer_records`)
+     */
+    pub(crate) async fn post_transfer(
+        &self,
+        from: &str,
+        to: &str,
+        units: Units,
+        memo: Option<String>,
+        transfer_id: Option<&str>,
+    ) -> Result<BasicResponse<String>, String> {
+        // Transfers of the same sender are evaluated one at a time, each one after the previous
+        // one was sent
+        let _sender_guard = if self.transfer_policies.is_empty() {
+            None
+        } else {
+            Some(self.locks.lock(&policy::sender_lock_key(from)).await)
+        };
+
+        if let Err(error_msg) = policy::evaluate(self, from, to, units, &memo).await {
+            return Ok(BasicResponse {
+                success: false,
+                error_msg,
+                data: None,
+            });
+        }
+
         let url = format!("{api}{route}", api = self.api, route = TRANSFER_UNITS);
 
         let body = json!({
@@ -164,19 +637,47 @@ impl ChainDB {
 
         let json_body = serde_json::to_string(&body).unwrap();
 
-        let client = reqwest::Client::new();
-        let response = client
+        let response = self
+            .client
             .post(&url)
             .header(CONTENT_TYPE, "application/json")
             .body(json_body)
             .send()
-            .await
-            .unwrap()
-            .text()
-            .await
-            .unwrap();
+            .await;
 
-        serde_json::from_str::<BasicResponse<String>>(&response).unwrap()
+        let response = match response {
+            Ok(response) => response.text().await,
+            Err(err) => Err(err),
+        };
+
+        let response = match response {
+            Ok(response) => serde_json::from_str::<BasicResponse<String>>(&response)
+                .map_err(|err| format!("Unexpected response from the server: {}", err))?,
+            Err(err) => return Err(err.to_string()),
+        };
+
+        if response.success {
+            // Transfers without an id get one, so their records can be told apart
+            let transfer_id = transfer_id.map(str::to_string).unwrap_or_else(|| {
+                random_string::generate(16, "abcdefghijklmnopqrstuvwxyz0123456789")
+            });
+            transfer_records::record(self, from, to, units, memo, &transfer_id).await;
+        }
+
+        Ok(response)
+    }
+
+    /**
+     * Transfer units for several users. Balances are checked before sending any transfer.
+     * Transfers whose outcome is unknown are reported in `unknown` and have to be resolved with
+     * `resolve_transfer`; in the all-or-nothing mode nothing is transferred back until they are
+     */
+    pub async fn transfer_batch(
+        &self,
+        transfers: Vec<Transfer>,
+        options: BatchOptions,
+    ) -> BasicResponse<BatchTransferReport> {
+        batch::transfer_batch(self, transfers, options).await
     }
 
     /**
@@ -196,7 +697,15 @@ impl ChainDB {
 
         let res_json = reqwest::get(url).await.unwrap().text().await.unwrap();
 
-        serde_json::from_str::<BasicResponse<TransferUnitsRegistry>>(&res_json).unwrap()
+        let mut response =
+            serde_json::from_str::<BasicResponse<TransferUnitsRegistry>>(&res_json).unwrap();
+
+        // Add the memo and time kept by the client
+        if let Some(record) = response.data.as_mut() {
+            transfer_records::enrich_last(self, user_id, record).await;
+        }
+
+        response
     }
 
     /**
@@ -216,70 +725,251 @@ impl ChainDB {
 
         let res_json = reqwest::get(url).await.unwrap().text().await.unwrap();
 
-        serde_json::from_str::<BasicResponse<Vec<TransferUnitsRegistry>>>(&res_json).unwrap()
+        let mut response =
+            serde_json::from_str::<BasicResponse<Vec<TransferUnitsRegistry>>>(&res_json).unwrap();
+
+        // Add the memo and time kept by the client
+        if let Some(records) = response.data.as_mut() {
+            transfer_records::enrich(self, user_id, records).await;
+        }
+
+        response
     }
 
     /**
-     * Initialize a table, fetching its more updated data
+     * Fetch the transfer records kept by the client for a user: the transfers made through this
+     * library, with their memo and transfer id. The server doesn't keep them
      */
-    pub async fn get_table<Model: DeserializeOwned + Serialize>(
+    pub async fn get_transfer_records_by_user_id(
         &self,
-        table_name: &'static str,
-        get_model_instance: fn() -> Model,
-    ) -> Table<Model> {
-        Table::get(&self, table_name, get_model_instance).await
+        user_id: &str,
+    ) -> BasicResponse<Vec<TransferUnitsRegistry>> {
+        BasicResponse {
+            success: true,
+            error_msg: String::new(),
+            data: Some(transfer_records::get_records(self, user_id).await),
+        }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::test::TestTable;
 
-    use super::*;
+    /**
+     * Add units of a named asset to a user account. Returns the new balance of the asset
+     */
+    pub async fn grant_asset(&self, user_id: &str, asset: &str, units: Units) -> BasicResponse<Units> {
+        assets::grant(self, user_id, asset, units).await
+    }
 
-    // WARNING: Make sure the ChainDB is running.
-    fn random_str() -> String {
-        let charset = "abcdefghijklmnopqrstuvwxyz";
-        random_string::generate(12, charset)
+    /**
+     * Transfer units of a named asset between users
+     */
+    pub async fn transfer_asset(
+        &self,
+        from: &str,
+        to: &str,
+        asset: &str,
+        units: Units,
+    ) -> BasicResponse<String> {
+        assets::transfer(self, from, to, asset, units).await
     }
 
-    // #[tokio::test]
-    async fn create_user_account() {
-        let db = ChainDB::connect(None, "test-db", "root", "1234");
-        let random_user_name = random_str();
-        let new_user = db
-            .create_user_account(random_user_name.as_str(), "fake123pass", Some(10), None)
-            .await;
+    /**
+     * Get the balances of the named assets of a user
+     */
+    pub async fn get_asset_balances(&self, user_id: &str) -> BasicResponse<BTreeMap<String, Units>> {
+        assets::get_balances(self, user_id).await
+    }
 
-        assert_eq!(new_user.success, true, "Testing account creation");
+    /**
+     * Fetch all the named asset transfer records by user. If `asset` is given, only the
+     * transfers of this asset are returned
+     */
+    pub async fn get_asset_transfers_by_user_id(
+        &self,
+        user_id: &str,
+        asset: Option<&str>,
+    ) -> BasicResponse<Vec<TransferUnitsRegistry>> {
+        assets::get_transfers(self, user_id, asset).await
     }
 
-    // #[tokio::test]
-    async fn create_user_account_with_name_already_taken_return_err() {
-        let db = ChainDB::connect(None, "test-db", "root", "1234");
-        let random_user_name = random_str();
-        let new_user = db
-            .create_user_account(random_user_name.as_str(), "fake123pass", Some(10), None)
-            .await;
+    /**
+     * Transfer units and persist the table data in one step. The data is only persisted if the
+     * table wasn't changed since it was read. If it can't be persisted, the units are
+     * transferred back to the sender
+     */
+    pub async fn transfer_units_and_persist<Model: Serialize>(
+        &self,
+        from: &str,
+        to: &str,
+        units: Units,
+        table: &Table<Model>,
+    ) -> BasicResponse<String> {
+        transfer_operation::transfer_and_persist(self, from, to, units, table).await
+    }
 
-        let new_user_2 = db
-            .create_user_account(random_user_name.as_str(), "fake123pass", Some(10), None)
-            .await;
+    /**
+     * Finish the "transfer units plus table update" operations interrupted in the middle.
+     * Run it on startup, while no other operation is in progress
+     */
+    pub async fn recover_transfer_operations(&self) -> BasicResponse<Vec<TransferOperation>> {
+        transfer_operation::recover_operations(self).await
+    }
 
-        assert_eq!(new_user.success, true);
-        assert_eq!(new_user_2.success, false);
-        assert_eq!(
-            new_user_2.error_msg,
-            "This user name is already taken".to_string()
-        );
+    /**
+     * Query the Transference of units Records by User, with filters and pagination
+     */
+    pub fn transfers(&self, user_id: &str) -> TransferQuery {
+        TransferQuery::new(self, user_id)
     }
 
-    // #[tokio::test]
+    /**
+     * Build the statement of an account for a period (range of transfer timestamps), with the
+     * running balance after each transfer and the period totals. Use `..` for the whole history
+     */
+    pub async fn account_statement(
+        &self,
+        user_id: &str,
+        timestamps: impl RangeBounds<u64>,
+    ) -> BasicResponse<AccountStatement> {
+        statement::account_statement(self, user_id, timestamps).await
+    }
+
+    /**
+     * Replay the transfer records of the given accounts and compare them against their current
+     * balances, reporting discrepancies, orphan transfers and the total supply
+     */
+    pub async fn reconcile_accounts(
+        &self,
+        accounts: &[AuditAccount],
+    ) -> BasicResponse<ReconciliationReport> {
+        reconciliation::reconcile(self, accounts).await
+    }
+
+    /**
+     * Initialize a table, fetching its more updated data
+     */
+    pub async fn get_table<Model: DeserializeOwned + Serialize>(
+        &self,
+        table_name: &'static str,
+        get_model_instance: fn() -> Model,
+    ) -> Table<Model> {
+        Table::get(&self, table_name, get_model_instance).await
+    }
+
+    /**
+     * Get a key-value store handle for the given namespace. Returns an error if the namespace
+     * is empty or has a `:` or `/`
+     */
+    pub fn kv(&self, namespace: &str) -> Result<KeyValueStore, String> {
+        KeyValueStore::new(self, namespace)
+    }
+
+    /**
+     * Get an append-only log, such as a list of messages
+     */
+    pub fn get_log<T: DeserializeOwned + Serialize>(&self, log_name: &str) -> AppendLog<T> {
+        AppendLog::new(self, log_name)
+    }
+
+    /**
+     * Get the repository of an aggregate type. Its events are stored as contract transactions.
+     * Returns an error if the name is empty or has a `:` or `/`
+     */
+    pub fn get_aggregates<A: Aggregate>(
+        &self,
+        name: &str,
+    ) -> Result<AggregateRepository<A>, String> {
+        AggregateRepository::new(self, name)
+    }
+
+    /**
+     * Get the escrow handle of an escrow account. Units of a buyer are locked in the escrow
+     * account until they are released to the seller or refunded. Returns an error if the
+     * account id has a `:` or `/`
+     */
+    pub fn escrow(&self, escrow_account: &str) -> Result<Escrow, String> {
+        Escrow::new(self, escrow_account)
+    }
+
+    /**
+     * Get the multi-signature handle of an account. Its transfers need the approval of M of
+     * its N approvers
+     */
+    pub fn multisig(&self, account: &str) -> MultiSig {
+        MultiSig::new(self, account)
+    }
+
+    /**
+     * Get the scheduler of once and recurring transfers. Call `start` to run it in a tokio task
+     */
+    pub fn transfer_scheduler(&self) -> TransferScheduler {
+        TransferScheduler::new(self)
+    }
+
+    /**
+     * Start a transaction to persist several tables at once
+     */
+    pub fn transaction(&self) -> TableTransaction {
+        TableTransaction::new(self)
+    }
+
+    /**
+     * Finish the transactions interrupted before they were fully committed, applying or undoing
+     * them. They are also finished when one of their tables is loaded. Returns the ids of the
+     * finished transactions
+     */
+    pub async fn recover_transactions(&self) -> BasicResponse<Vec<String>> {
+        transaction::recover_pending_commits(self, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::{CounterAggregate, CounterCommand, TestTable};
+
+    use super::*;
+
+    // WARNING: Make sure the ChainDB is running.
+    fn random_str() -> String {
+        let charset = "abcdefghijklmnopqrstuvwxyz";
+        random_string::generate(12, charset)
+    }
+
+    // #[tokio::test]
+    async fn create_user_account() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let random_user_name = random_str();
+        let new_user = db
+            .create_user_account(random_user_name.as_str(), "fake123pass", Some(Units::new(10)), None)
+            .await;
+
+        assert_eq!(new_user.success, true, "Testing account creation");
+    }
+
+    // #[tokio::test]
+    async fn create_user_account_with_name_already_taken_return_err() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let random_user_name = random_str();
+        let new_user = db
+            .create_user_account(random_user_name.as_str(), "fake123pass", Some(Units::new(10)), None)
+            .await;
+
+        let new_user_2 = db
+            .create_user_account(random_user_name.as_str(), "fake123pass", Some(Units::new(10)), None)
+            .await;
+
+        assert_eq!(new_user.success, true);
+        assert_eq!(new_user_2.success, false);
+        assert_eq!(
+            new_user_2.error_msg,
+            "This user name is already taken".to_string()
+        );
+    }
+
+    // #[tokio::test]
     async fn get_user_info_with_user_and_password() {
         let db = ChainDB::connect(None, "test-db", "root", "1234");
         let random_user_name = random_str();
         let new_user = db
-            .create_user_account(random_user_name.as_str(), "fake123pass", Some(10), None)
+            .create_user_account(random_user_name.as_str(), "fake123pass", Some(Units::new(10)), None)
             .await;
 
         let info_user_call = db
@@ -295,7 +985,7 @@ mod tests {
         let db = ChainDB::connect(None, "test-db", "root", "1234");
         let random_user_name = random_str();
         let new_user = db
-            .create_user_account(random_user_name.as_str(), "fake123pass", Some(10), None)
+            .create_user_account(random_user_name.as_str(), "fake123pass", Some(Units::new(10)), None)
             .await;
         let user_id = new_user.data.unwrap().id;
 
@@ -310,7 +1000,7 @@ mod tests {
         let db = ChainDB::connect(None, "test-db", "root", "1234");
         let random_user_name_1 = random_str();
         let new_user = db
-            .create_user_account(random_user_name_1.as_str(), "fake123pass", Some(10), None)
+            .create_user_account(random_user_name_1.as_str(), "fake123pass", Some(Units::new(10)), None)
             .await;
         let user_id_1 = new_user.data.unwrap().id;
 
@@ -320,7 +1010,7 @@ mod tests {
             .await;
         let user_id_2 = new_user_2.data.unwrap().id;
 
-        let tranference_response = db.transfer_units(&user_id_1, &user_id_2, 6).await;
+        let tranference_response = db.transfer_units(&user_id_1, &user_id_2, Units::new(6)).await;
         assert_eq!(tranference_response.success, true);
 
         // Ensure users have updated units
@@ -334,7 +1024,7 @@ mod tests {
         let db = ChainDB::connect(None, "test-db", "root", "1234");
         let random_user_name_1 = random_str();
         let new_user = db
-            .create_user_account(random_user_name_1.as_str(), "fake123pass", Some(10), None)
+            .create_user_account(random_user_name_1.as_str(), "fake123pass", Some(Units::new(10)), None)
             .await;
         let user_id_1 = new_user.data.unwrap().id;
 
@@ -344,7 +1034,7 @@ mod tests {
             .await;
         let user_id_2 = new_user_2.data.unwrap().id;
 
-        let tranference_response = db.transfer_units(&user_id_1, &user_id_2, 11).await;
+        let tranference_response = db.transfer_units(&user_id_1, &user_id_2, Units::new(11)).await;
         assert_eq!(
             tranference_response.error_msg,
             "Sender user does not have enough units"
@@ -355,7 +1045,7 @@ mod tests {
         let db = ChainDB::connect(None, "test-db", "root", "1234");
         let random_user_name_1 = random_str();
         let new_user = db
-            .create_user_account(random_user_name_1.as_str(), "fake123pass", Some(10), None)
+            .create_user_account(random_user_name_1.as_str(), "fake123pass", Some(Units::new(10)), None)
             .await;
         let user_id_1 = new_user.data.unwrap().id;
 
@@ -365,7 +1055,7 @@ mod tests {
             .await;
         let user_id_2 = new_user_2.data.unwrap().id;
 
-        let tranference_response = db.transfer_units(&user_id_1, &user_id_2, 6).await;
+        let tranference_response = db.transfer_units(&user_id_1, &user_id_2, Units::new(6)).await;
         assert_eq!(tranference_response.success, true);
 
         let last_transfer_record = db.get_transfer_by_user_id(&user_id_1).await;
@@ -380,7 +1070,7 @@ mod tests {
         let db = ChainDB::connect(None, "test-db", "root", "1234");
         let random_user_name_1 = random_str();
         let new_user = db
-            .create_user_account(random_user_name_1.as_str(), "fake123pass", Some(10), None)
+            .create_user_account(random_user_name_1.as_str(), "fake123pass", Some(Units::new(10)), None)
             .await;
         let user_id_1 = new_user.data.unwrap().id;
 
@@ -390,8 +1080,8 @@ mod tests {
             .await;
         let user_id_2 = new_user_2.data.unwrap().id;
 
-        let _ = db.transfer_units(&user_id_1, &user_id_2, 6).await;
-        let _ = db.transfer_units(&user_id_1, &user_id_2, 2).await;
+        let _ = db.transfer_units(&user_id_1, &user_id_2, Units::new(6)).await;
+        let _ = db.transfer_units(&user_id_1, &user_id_2, Units::new(2)).await;
 
         let last_transfer_records = db.get_all_transfers_by_user_id(&user_id_1).await;
         let transfers = last_transfer_records.data.unwrap();
@@ -445,6 +1135,1102 @@ mod tests {
         
     }
 
+    async fn key_value_store_set_get_delete() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let kv = db.kv(&random_str()).unwrap();
+
+        assert_eq!(kv.get::<bool>("flag").await, None);
+        assert!(kv.set("flag", &true).await.success);
+        assert_eq!(kv.get::<bool>("flag").await, Some(true));
+
+        let cas_fail = kv.compare_and_set("flag", Some(&false), &false).await;
+        assert!(!cas_fail.success);
+        let cas_ok = kv.compare_and_set("flag", Some(&true), &false).await;
+        assert!(cas_ok.success);
+        assert_eq!(kv.get::<bool>("flag").await, Some(false));
+        assert_eq!(kv.keys().await, vec!["flag".to_string()]);
+
+        assert!(kv.delete("flag").await.success);
+        assert_eq!(kv.get::<bool>("flag").await, None);
+        assert!(kv.keys().await.is_empty());
+        assert_eq!(kv.history::<bool>("flag", 10).await.len(), 3);
+    }
+
+    async fn append_log_read_tail_and_stream() {
+        use futures::StreamExt;
+
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let log = db.get_log::<String>(&random_str());
+
+        assert!(log.is_empty().await);
+        for message in ["Hi", "Hello", "Ola"] {
+            assert!(log.append(&message.to_string()).await.success);
+        }
+        assert_eq!(log.len().await, 3);
+
+        let items = log.read(1..3).await;
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].item, "Hello");
+        assert_eq!(items[1].item, "Ola");
+
+        let last = log.tail(1).await;
+        assert_eq!(last[0].seq, 2);
+
+        let streamed: Vec<_> = log.stream_from(0).take(3).collect().await;
+        assert_eq!(streamed.iter().map(|i| i.seq).collect::<Vec<_>>(), vec![0, 1, 2]);
+
+        // Concurrent appenders of the same process get different sequence numbers
+        let feed = db.get_log::<String>(&random_str());
+        let appends = ["a", "b", "c"].map(|item| {
+            let feed = feed.clone();
+            async move { feed.append(&item.to_string()).await.data.unwrap() }
+        });
+        let mut seqs = futures::future::join_all(appends).await;
+        seqs.sort();
+        assert_eq!(seqs, vec![0, 1, 2]);
+    }
+
+    async fn aggregate_execute_replay_and_snapshot() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let counters = db
+            .get_aggregates::<CounterAggregate>("counters")
+            .unwrap()
+            .with_snapshot_every(2);
+        let id = random_str();
+
+        assert!(counters.execute(&id, CounterCommand::Add(5)).await.success);
+        assert!(counters.execute(&id, CounterCommand::Add(3)).await.success);
+        let rejected = counters.execute(&id, CounterCommand::Remove(10)).await;
+        assert_eq!(rejected.error_msg, "Counter can not be negative");
+        assert!(counters.execute(&id, CounterCommand::Remove(2)).await.success);
+
+        let counter = counters.load(&id).await;
+        assert_eq!(counter.version, 3);
+        assert_eq!(counter.state.value, 6);
+        assert_eq!(counters.events_since(&id, 2).await.len(), 1);
+
+        // Concurrent commands are either stored one after the other or rejected
+        let executions = (0..3).map(|_| counters.execute(&id, CounterCommand::Add(1)));
+        let stored = futures::future::join_all(executions)
+            .await
+            .iter()
+            .filter(|executed| executed.success)
+            .count() as u64;
+        assert_eq!(counters.load(&id).await.version, 3 + stored);
+
+        assert!(db.get_aggregates::<CounterAggregate>("counters:v2").is_err());
+    }
+
+    async fn transaction_commits_several_tables() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let mut orders = db.get_table("tx-orders", TestTable::new).await;
+        let mut inventory = db.get_table("tx-inventory", TestTable::new).await;
+
+        let year = orders.table.year.wrapping_add(1);
+        orders.table.year = year;
+        inventory.table.year = year;
+
+        let commit = db.transaction().stage(&orders).stage(&inventory).commit().await;
+        assert!(commit.success);
+
+        let orders = db.get_table("tx-orders", TestTable::new).await;
+        let inventory = db.get_table("tx-inventory", TestTable::new).await;
+        assert_eq!(orders.table.year, year);
+        assert_eq!(inventory.table.year, year);
+    }
+
+    async fn transfer_units_and_persist_table() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let buyer = db
+            .create_user_account(random_str().as_str(), "fake123pass", Some(Units::new(10)), None)
+            .await;
+        let buyer_id = buyer.data.unwrap().id;
+        let seller = db
+            .create_user_account(random_str().as_str(), "fake123pass", None, None)
+            .await;
+        let seller_id = seller.data.unwrap().id;
+
+        let mut purchases = db.get_table("purchases", TestTable::new).await;
+        purchases.table.greeting = buyer_id.clone();
+
+        let res = db
+            .transfer_units_and_persist(&buyer_id, &seller_id, Units::new(4), &purchases)
+            .await;
+        assert!(res.success);
+
+        let purchases = db.get_table("purchases", TestTable::new).await;
+        assert_eq!(purchases.table.greeting, buyer_id);
+        assert_eq!(db.get_user_account_by_id(&seller_id).await.data.unwrap().units, 4);
+
+        // A table changed since it was read is not overwritten, the transfer is reversed
+        let mut stale = db.get_table("purchases", TestTable::new).await;
+        let mut newer = db.get_table("purchases", TestTable::new).await;
+        newer.table.greeting = random_str();
+        newer.persist().await;
+
+        stale.table.greeting = seller_id.clone();
+        let res = db
+            .transfer_units_and_persist(&buyer_id, &seller_id, Units::new(2), &stale)
+            .await;
+        assert!(res
+            .error_msg
+            .starts_with("Table could not be persisted, the transfer was reversed"));
+        let purchases = db.get_table("purchases", TestTable::new).await;
+        assert_eq!(purchases.table.greeting, newer.table.greeting);
+        assert_eq!(db.get_user_account_by_id(&seller_id).await.data.unwrap().units, 4);
+        assert!(db.recover_transfer_operations().await.success);
+    }
+
+    #[test]
+    fn units_decimal_parsing_and_formatting() {
+        assert_eq!(Units::from_decimal("12.34", 2), Ok(Units::new(1234)));
+        assert_eq!(Units::from_decimal("12.3", 2), Ok(Units::new(1230)));
+        assert_eq!(Units::from_decimal("7", 2), Ok(Units::new(700)));
+        assert_eq!(
+            Units::from_decimal("1.234", 2),
+            Err(UnitsError::TooManyDecimals { max: 2 })
+        );
+        assert_eq!(Units::from_decimal("-1", 2), Err(UnitsError::InvalidDigit));
+        assert_eq!(
+            Units::from_decimal("18446744073709551615", 2),
+            Err(UnitsError::Overflow)
+        );
+
+        assert_eq!(Units::new(1234).to_decimal(2), "12.34");
+        assert_eq!(Units::new(5).to_decimal(2), "0.05");
+        assert_eq!(Units::new(5).to_decimal(0), "5");
+    }
+
+    #[test]
+    fn units_checked_arithmetic_and_wire_format() {
+        assert_eq!(Units::new(2).checked_add(Units::new(3)), Some(Units::new(5)));
+        assert_eq!(Units::new(u64::MAX).checked_add(Units::new(1)), None);
+        assert_eq!(Units::new(2).checked_sub(Units::new(3)), None);
+
+        assert_eq!(serde_json::to_string(&Units::new(42)).unwrap(), "42");
+        assert_eq!(serde_json::from_str::<Units>("42").unwrap(), Units::new(42));
+    }
+
+    async fn transfer_named_assets_between_two_users() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let mut initial_assets = BTreeMap::new();
+        initial_assets.insert("gold".to_string(), Units::new(10));
+        initial_assets.insert("gems".to_string(), Units::new(3));
+
+        let new_user = db
+            .create_user_account_with_assets(random_str().as_str(), "fake123pass", None, None, initial_assets)
+            .await;
+        let user_id_1 = new_user.data.unwrap().id;
+        let new_user_2 = db
+            .create_user_account(random_str().as_str(), "fake123pass", None, None)
+            .await;
+        let user_id_2 = new_user_2.data.unwrap().id;
+
+        assert!(db.transfer_asset(&user_id_1, &user_id_2, "gold", Units::new(6)).await.success);
+        let not_enough = db.transfer_asset(&user_id_1, &user_id_2, "gems", Units::new(4)).await;
+        assert_eq!(not_enough.error_msg, "Sender user does not have enough gems");
+
+        let balances_1 = db.get_asset_balances(&user_id_1).await.data.unwrap();
+        let balances_2 = db.get_asset_balances(&user_id_2).await.data.unwrap();
+        assert_eq!(balances_1["gold"], 4);
+        assert_eq!(balances_1["gems"], 3);
+        assert_eq!(balances_2["gold"], 6);
+
+        let transfers = db.get_asset_transfers_by_user_id(&user_id_2, Some("gold")).await;
+        assert_eq!(transfers.data.unwrap().len(), 1);
+    }
+
+    async fn transfer_batch_with_all_or_nothing() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let payer = db
+            .create_user_account(random_str().as_str(), "fake123pass", Some(Units::new(10)), None)
+            .await;
+        let payer_id = payer.data.unwrap().id;
+
+        let mut receivers = vec![];
+        for _ in 0..3 {
+            let receiver = db
+                .create_user_account(random_str().as_str(), "fake123pass", None, None)
+                .await;
+            receivers.push(receiver.data.unwrap().id);
+        }
+
+        let transfers: Vec<Transfer> = receivers
+            .iter()
+            .map(|id| Transfer::new(&payer_id, id, Units::new(3)))
+            .collect();
+        let report = db.transfer_batch(transfers, BatchOptions::default()).await;
+        assert!(report.success);
+        let report = report.data.unwrap();
+        assert_eq!(report.succeeded, 3);
+        assert_eq!(report.reverted, 0);
+
+        // Not enough units left for both transfers
+        let transfers = vec![
+            Transfer::new(&payer_id, &receivers[0], Units::new(1)),
+            Transfer::new(&payer_id, &receivers[1], Units::new(1)),
+        ];
+        let options = BatchOptions {
+            all_or_nothing: true,
+            ..BatchOptions::default()
+        };
+        let rejected = db.transfer_batch(transfers, options).await;
+        assert!(!rejected.success);
+        assert!(rejected.data.is_none());
+    }
+
+    async fn transfer_units_with_id_is_idempotent() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let new_user = db
+            .create_user_account(random_str().as_str(), "fake123pass", Some(Units::new(10)), None)
+            .await;
+        let user_id_1 = new_user.data.unwrap().id;
+        let new_user_2 = db
+            .create_user_account(random_str().as_str(), "fake123pass", None, None)
+            .await;
+        let user_id_2 = new_user_2.data.unwrap().id;
+
+        let transfer_id = random_str();
+        let first = db
+            .transfer_units_with_id(&transfer_id, &user_id_1, &user_id_2, Units::new(6), None)
+            .await;
+        let retry = db
+            .transfer_units_with_id(&transfer_id, &user_id_1, &user_id_2, Units::new(6), None)
+            .await;
+        assert!(first.success);
+        assert!(retry.success);
+
+        // The transfer data of an id can't change
+        let other = db
+            .transfer_units_with_id(&transfer_id, &user_id_1, &user_id_2, Units::new(1), None)
+            .await;
+        assert!(!other.success);
+        assert!(!db.resolve_transfer(&transfer_id, false).await.success);
+
+        let info_user_2 = db.get_user_account_by_id(&user_id_2).await;
+        assert_eq!(info_user_2.data.unwrap().units, 6);
+
+        let settled = db.get_transfer_by_transfer_id(&transfer_id).await;
+        assert_eq!(settled.data.unwrap().transfer_id, Some(transfer_id));
+        assert!(!db.get_transfer_by_transfer_id(&random_str()).await.success);
+    }
+
+    async fn transfer_records_with_memo_and_transfer_id() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let new_user = db
+            .create_user_account(random_str().as_str(), "fake123pass", Some(Units::new(10)), None)
+            .await;
+        let user_id_1 = new_user.data.unwrap().id;
+        let new_user_2 = db
+            .create_user_account(random_str().as_str(), "fake123pass", None, None)
+            .await;
+        let user_id_2 = new_user_2.data.unwrap().id;
+
+        let memo = Some("Order #1234".to_string());
+        let _ = db.transfer_units(&user_id_1, &user_id_2, Units::new(2)).await;
+        let _ = db.transfer_units_with_memo(&user_id_1, &user_id_2, Units::new(3), memo.clone()).await;
+
+        let records = db.get_transfer_records_by_user_id(&user_id_2).await.data.unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].memo, memo);
+        assert!(records.iter().all(|t| t.transfer_id.is_some()));
+        // Block information of the stored records
+        assert!(records.iter().all(|t| t.block_height.is_some() && t.block_hash.is_some()));
+
+        // Matched with the records of the server by users and amounts
+        let transfers = db.get_all_transfers_by_user_id(&user_id_1).await.data.unwrap();
+        assert_eq!(transfers.len(), 2);
+        assert_eq!(transfers[1].memo, memo);
+        assert!(transfers.iter().all(|t| t.transfer_id.is_some()));
+
+        let last = db.get_transfer_by_user_id(&user_id_2).await.data.unwrap();
+        assert_eq!(last.memo, memo);
+    }
+
+    async fn query_transfer_records_with_filters_and_pages() {
+        use futures::StreamExt;
+
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let new_user = db
+            .create_user_account(random_str().as_str(), "fake123pass", Some(Units::new(10)), None)
+            .await;
+        let user_id_1 = new_user.data.unwrap().id;
+        let new_user_2 = db
+            .create_user_account(random_str().as_str(), "fake123pass", None, None)
+            .await;
+        let user_id_2 = new_user_2.data.unwrap().id;
+
+        for units in [1, 2, 3] {
+            let _ = db.transfer_units(&user_id_1, &user_id_2, Units::new(units)).await;
+        }
+        let _ = db.transfer_units(&user_id_2, &user_id_1, Units::new(1)).await;
+
+        let outgoing = db
+            .transfers(&user_id_1)
+            .direction(TransferDirection::Outgoing)
+            .units(Units::new(2)..)
+            .order(SortOrder::Descending)
+            .fetch()
+            .await
+            .data
+            .unwrap();
+        let units: Vec<_> = outgoing.records.iter().map(|t| t.units.amount()).collect();
+        assert_eq!(units, vec![3, 2]);
+
+        let first_page = db.transfers(&user_id_1).limit(3).fetch().await.data.unwrap();
+        assert_eq!(first_page.records.len(), 3);
+        let cursor = first_page.next_cursor.unwrap();
+        let second_page = db.transfers(&user_id_1).limit(3).after(&cursor).unwrap().fetch().await;
+        let second_page = second_page.data.unwrap();
+        assert_eq!(second_page.records.len(), 1);
+        assert!(second_page.next_cursor.is_none());
+        assert!(db.transfers(&user_id_1).after("not-a-cursor").is_err());
+
+        let all: Vec<_> = db.transfers(&user_id_1).limit(1).stream().collect().await;
+        assert_eq!(all.len(), 4);
+        assert!(all.iter().all(|record| record.is_ok()));
+
+        // The time of the transfers made through this library is known
+        let recent = db.transfers(&user_id_1).timestamps(1..).fetch().await;
+        assert_eq!(recent.data.unwrap().records.len(), 4);
+    }
+
+    async fn account_statement_with_running_balances() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let new_user = db
+            .create_user_account(random_str().as_str(), "fake123pass", Some(Units::new(10)), None)
+            .await;
+        let user_id_1 = new_user.data.unwrap().id;
+        let new_user_2 = db
+            .create_user_account(random_str().as_str(), "fake123pass", None, None)
+            .await;
+        let user_id_2 = new_user_2.data.unwrap().id;
+
+        let _ = db.transfer_units(&user_id_1, &user_id_2, Units::new(6)).await;
+        let _ = db.transfer_units(&user_id_2, &user_id_1, Units::new(2)).await;
+
+        let statement = db.account_statement(&user_id_1, ..).await.data.unwrap();
+        assert_eq!(statement.opening_balance, 10);
+        assert_eq!(statement.closing_balance, 6);
+        assert_eq!(statement.total_in, 2);
+        assert_eq!(statement.total_out, 6);
+        assert_eq!(statement.net(), -4);
+        let balances: Vec<_> = statement.lines.iter().map(|l| l.balance_after.amount()).collect();
+        assert_eq!(balances, vec![4, 6]);
+        assert_eq!(statement.to_csv().lines().count(), 3);
+
+        // The timestamps of the transfers made through this library are known
+        let period = db.account_statement(&user_id_1, 1..).await.data.unwrap();
+        assert_eq!(period.lines.len(), 2);
+        assert_eq!(period.opening_balance, 10);
+    }
+
+    async fn reconcile_accounts_after_transfers() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let new_user = db
+            .create_user_account(random_str().as_str(), "fake123pass", Some(Units::new(10)), None)
+            .await;
+        let user_id_1 = new_user.data.unwrap().id;
+        let new_user_2 = db
+            .create_user_account(random_str().as_str(), "fake123pass", None, None)
+            .await;
+        let user_id_2 = new_user_2.data.unwrap().id;
+
+        let _ = db.transfer_units(&user_id_1, &user_id_2, Units::new(4)).await;
+
+        let accounts = [
+            AuditAccount::new(&user_id_1, Some(Units::new(10))),
+            AuditAccount::new(&user_id_2, Some(Units::ZERO)),
+        ];
+        let report = db.reconcile_accounts(&accounts).await.data.unwrap();
+        assert!(report.is_consistent());
+        assert_eq!(report.total_supply, 10);
+        assert_eq!(report.total_initial_units, Some(Units::new(10)));
+
+        // Wrong initial units are reported
+        let accounts = [AuditAccount::new(&user_id_2, Some(Units::new(1)))];
+        let report = db.reconcile_accounts(&accounts).await.data.unwrap();
+        assert!(!report.is_consistent());
+        assert_eq!(report.discrepancies.len(), 1);
+    }
+
+    async fn escrow_hold_release_and_refund() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let buyer = db
+            .create_user_account(random_str().as_str(), "fake123pass", Some(Units::new(10)), None)
+            .await;
+        let buyer_id = buyer.data.unwrap().id;
+        let seller = db
+            .create_user_account(random_str().as_str(), "fake123pass", None, None)
+            .await;
+        let seller_id = seller.data.unwrap().id;
+        let escrow_account = db
+            .create_user_account(random_str().as_str(), "fake123pass", None, None)
+            .await;
+        let escrow = db.escrow(&escrow_account.data.unwrap().id).unwrap();
+
+        let hold = escrow
+            .create_hold(&buyer_id, &seller_id, Units::new(4), "order 1", None)
+            .await
+            .data
+            .unwrap();
+        assert_eq!(hold.status, HoldStatus::Open);
+        assert_eq!(escrow.open_holds().await.len(), 1);
+
+        let released = escrow.release(&hold.id).await.data.unwrap();
+        assert_eq!(released.status, HoldStatus::Released);
+        assert!(!escrow.refund(&hold.id).await.success);
+
+        // Expired holds can only be refunded
+        let hold = escrow
+            .create_hold(&buyer_id, &seller_id, Units::new(3), "order 2", Some(1))
+            .await
+            .data
+            .unwrap();
+        assert!(!escrow.release(&hold.id).await.success);
+        let refunded = escrow.refund_expired().await.data.unwrap();
+        assert_eq!(refunded.len(), 1);
+        assert!(escrow.open_holds().await.is_empty());
+
+        let buyer = db.get_user_account_by_id(&buyer_id).await.data.unwrap();
+        assert_eq!(buyer.units, 6);
+        let seller = db.get_user_account_by_id(&seller_id).await.data.unwrap();
+        assert_eq!(seller.units, 4);
+    }
+
+    async fn scheduled_and_recurring_transfers() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let new_user = db
+            .create_user_account(random_str().as_str(), "fake123pass", Some(Units::new(10)), None)
+            .await;
+        let user_id_1 = new_user.data.unwrap().id;
+        let new_user_2 = db
+            .create_user_account(random_str().as_str(), "fake123pass", None, None)
+            .await;
+        let user_id_2 = new_user_2.data.unwrap().id;
+
+        let scheduler = db.transfer_scheduler();
+        let once = scheduler
+            .schedule_once(Transfer::new(&user_id_1, &user_id_2, Units::new(1)), 1)
+            .await
+            .data
+            .unwrap();
+        // Both runs are already due, so they're made on the first check
+        let recurring = scheduler
+            .schedule_recurring(
+                Transfer::new(&user_id_1, &user_id_2, Units::new(2)).with_memo("allowance"),
+                1,
+                std::time::Duration::from_secs(3600),
+                Some(2),
+            )
+            .await
+            .data
+            .unwrap();
+
+        let runs = scheduler.run_due().await.data.unwrap();
+        assert!(runs.iter().all(|run| run.success));
+        assert_eq!(scheduler.runs(&once.id).await.len(), 1);
+        assert_eq!(scheduler.runs(&recurring.id).await.len(), 2);
+
+        // A new scheduler (e.g. after a restart) doesn't run them again
+        let runs = db.transfer_scheduler().run_due().await.data.unwrap();
+        assert!(runs.iter().all(|run| run.schedule_id != once.id && run.schedule_id != recurring.id));
+
+        let user = db.get_user_account_by_id(&user_id_2).await.data.unwrap();
+        assert_eq!(user.units, 5);
+    }
+
+    async fn multisig_transfer_needs_approvals() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let treasury_name = random_str();
+        let treasury = db
+            .create_user_account(&treasury_name, "fake123pass", Some(Units::new(10)), None)
+            .await;
+        let treasury_id = treasury.data.unwrap().id;
+        let receiver = db
+            .create_user_account(random_str().as_str(), "fake123pass", None, None)
+            .await;
+        let receiver_id = receiver.data.unwrap().id;
+
+        let mut approvers = vec![];
+        for _ in 0..3 {
+            let user_name = random_str();
+            let user = db
+                .create_user_account(&user_name, "fake123pass", None, None)
+                .await;
+            approvers.push((user_name, user.data.unwrap().id));
+        }
+        let approver_ids: Vec<&str> = approvers.iter().map(|(_, id)| id.as_str()).collect();
+
+        let multisig = db.multisig(&treasury_id);
+        // Only the owner of the account can configure it
+        let not_owner = multisig.configure(&approvers[0].0, "fake123pass", &approver_ids, 2).await;
+        assert!(!not_owner.success);
+        let configured = multisig.configure(&treasury_name, "fake123pass", &approver_ids, 2).await;
+        assert!(configured.success);
+
+        let proposal = multisig
+            .propose(&approvers[0].0, "fake123pass", &receiver_id, Units::new(4), None)
+            .await
+            .data
+            .unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Pending);
+        assert_eq!(multisig.pending_proposals().await.len(), 1);
+
+        // Wrong credentials and users outside the approvers can't approve
+        assert!(!multisig.approve(&proposal.id, &approvers[1].0, "wrong").await.success);
+        let outsider = db.get_user_account_by_id(&receiver_id).await.data.unwrap();
+        assert!(!multisig.approve(&proposal.id, &outsider.user_name, "fake123pass").await.success);
+
+        let proposal = multisig
+            .approve(&proposal.id, &approvers[1].0, "fake123pass")
+            .await
+            .data
+            .unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Executed);
+        let actions: Vec<_> = proposal.history.iter().map(|e| e.action.clone()).collect();
+        assert_eq!(
+            actions,
+            vec![
+                ProposalAction::Proposed,
+                ProposalAction::Approved,
+                ProposalAction::Approved,
+                ProposalAction::Executed
+            ]
+        );
+
+        let receiver = db.get_user_account_by_id(&receiver_id).await.data.unwrap();
+        assert_eq!(receiver.units, 4);
+
+        // Rotating the approvers keeps every configuration
+        let rotated = multisig
+            .configure(&treasury_name, "fake123pass", &approver_ids[1..], 2)
+            .await
+            .data
+            .unwrap();
+        assert_eq!(rotated.configurations.len(), 2);
+        let removed = multisig
+            .propose(&approvers[0].0, "fake123pass", &receiver_id, Units::new(1), None)
+            .await;
+        assert!(!removed.success);
+    }
+
+    #[test]
+    fn transfer_policies_reject_with_reason() {
+        let record = |units: u64, timestamp: u64| TransferUnitsRegistry {
+            from: "alice".to_string(),
+            to: "bob".to_string(),
+            units: Units::new(units),
+            asset: None,
+            transfer_id: None,
+            memo: None,
+            timestamp: Some(timestamp),
+            block_height: None,
+            block_hash: None,
+        };
+        let context = TransferContext {
+            from: "alice".to_string(),
+            to: "bob".to_string(),
+            units: Units::new(5),
+            memo: None,
+            now: 100_000,
+            // The oldest one is out of the last 24 hours
+            history: vec![record(50, 1_000), record(3, 99_000), record(2, 99_990)],
+        };
+
+        assert!(MaxTransferAmount::new(Units::new(5)).evaluate(&context).is_ok());
+        assert!(MaxTransferAmount::new(Units::new(4)).evaluate(&context).is_err());
+        assert!(DailyLimit::new(Units::new(10)).evaluate(&context).is_ok());
+        assert!(DailyLimit::new(Units::new(9)).evaluate(&context).is_err());
+        assert!(AllowList::new(&["bob"]).evaluate(&context).is_ok());
+        assert!(AllowList::new(&["carol"]).evaluate(&context).is_err());
+        assert!(DenyList::new(&["bob"]).evaluate(&context).is_err());
+        assert!(Velocity::new(2, std::time::Duration::from_secs(60)).evaluate(&context).is_ok());
+        assert!(Velocity::new(1, std::time::Duration::from_secs(60)).evaluate(&context).is_err());
+        assert!(DenyList::new(&["bob"]).only_for("carol").evaluate(&context).is_ok());
+
+        // Records sent by the server have no timestamp, they can't be placed in the window
+        let server_record = TransferUnitsRegistry {
+            timestamp: None,
+            ..record(50, 0)
+        };
+        let context = TransferContext {
+            history: vec![server_record.clone(), server_record, record(3, 99_000)],
+            ..context
+        };
+        assert!(DailyLimit::new(Units::new(8)).evaluate(&context).is_ok());
+        assert!(DailyLimit::new(Units::new(7)).evaluate(&context).is_err());
+        assert!(Velocity::new(2, std::time::Duration::from_secs(3600)).evaluate(&context).is_ok());
+    }
+
+    async fn transfer_policies_are_evaluated_before_transfers() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let new_user = db
+            .create_user_account(random_str().as_str(), "fake123pass", Some(Units::new(10)), None)
+            .await;
+        let user_id_1 = new_user.data.unwrap().id;
+        let new_user_2 = db
+            .create_user_account(random_str().as_str(), "fake123pass", None, None)
+            .await;
+        let user_id_2 = new_user_2.data.unwrap().id;
+
+        let db = db
+            .with_transfer_policy(MaxTransferAmount::new(Units::new(5)))
+            .with_transfer_policy(DenyList::new(&["blocked-user"]));
+
+        let rejected = db.transfer_units(&user_id_1, &user_id_2, Units::new(6)).await;
+        assert!(!rejected.success);
+        assert!(rejected.error_msg.starts_with("Transfer rejected by MaxTransferAmount"));
+
+        let transfer = db.transfer_units(&user_id_1, &user_id_2, Units::new(5)).await;
+        assert!(transfer.success);
+
+        let user = db.get_user_account_by_id(&user_id_1).await.data.unwrap();
+        assert_eq!(user.units, 5);
+    }
+
+    async fn user_session_transfers_tables_and_logout() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let user_name = random_str();
+        let _ = db
+            .create_user_account(&user_name, "fake123pass", Some(Units::new(10)), None)
+            .await;
+        let new_user_2 = db
+            .create_user_account(random_str().as_str(), "fake123pass", None, None)
+            .await;
+        let user_id_2 = new_user_2.data.unwrap().id;
+
+        assert!(!db.login(&user_name, "wrong").await.success);
+        let mut session = db.login(&user_name, "fake123pass").await.data.unwrap();
+        assert!(session.is_active());
+
+        assert!(session.transfer(&user_id_2, Units::new(3), None).await.success);
+        let account = session.refresh().await.data.unwrap();
+        assert_eq!(account.units, 7);
+        assert_eq!(session.account().units, 7);
+
+        let page = session.transfers().data.unwrap().fetch().await.data.unwrap();
+        assert_eq!(page.records.len(), 1);
+
+        let mut table = session
+            .get_table("greeting", TestTable::new)
+            .await
+            .data
+            .unwrap();
+        table.table.set_greeting("Hello".to_string());
+        table.persist().await;
+
+        let clone = session.clone();
+        session.logout();
+        assert!(!clone.is_active());
+        let after_logout = clone.transfer(&user_id_2, Units::new(1), None).await;
+        assert_eq!(after_logout.error_msg, "Session was logged out");
+
+        let expired = db
+            .login_for(&user_name, "fake123pass", std::time::Duration::ZERO)
+            .await
+            .data
+            .unwrap();
+        assert!(!expired.is_active());
+    }
+
+    async fn change_password_migrates_units_and_tables() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let user_name = random_str();
+        let _ = db
+            .create_user_account(&user_name, "fake123pass", Some(Units::new(10)), None)
+            .await;
+
+        let session = db.login(&user_name, "fake123pass").await.data.unwrap();
+        let mut table = session.get_table("greeting", TestTable::new).await.data.unwrap();
+        table.table.set_greeting("Hello".to_string());
+        table.persist().await;
+
+        let wrong = db.change_password(&user_name, "wrong", "new123pass").await;
+        assert!(!wrong.success);
+
+        let account = db
+            .change_password(&user_name, "fake123pass", "new123pass")
+            .await
+            .data
+            .unwrap();
+        assert_eq!(account.units, 10);
+        // The new account is signed in with the same user name
+        assert_eq!(account.user_name, user_name);
+        assert!(!db.login(&user_name, "fake123pass").await.success);
+
+        let session = db.login(&user_name, "new123pass").await.data.unwrap();
+        assert_eq!(session.user_id(), account.id);
+        let table = session.get_table("greeting", TestTable::new).await.data.unwrap();
+        assert_eq!(table.table.greeting, "Hello");
+    }
+
+    async fn password_hint_and_account_recovery() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let user_name = random_str();
+        let account = db
+            .create_user_account_with_recovery(
+                &user_name,
+                "fake123pass",
+                Some(Units::new(3)),
+                Some("fake and 123".to_string()),
+            )
+            .await
+            .data
+            .unwrap();
+        assert_eq!(account.recovery_codes.len(), 8);
+        assert_eq!(db.get_password_hint(&user_name).await.data.unwrap(), "fake and 123");
+
+        let code = account.recovery_codes[0].clone();
+        assert!(!db.recover_account(&user_name, "not-a-code", "new123pass").await.success);
+
+        let recovered = db
+            .recover_account(&user_name, &code.to_uppercase(), "new123pass")
+            .await
+            .data
+            .unwrap();
+        assert_eq!(recovered.units, 3);
+        assert!(db.login(&user_name, "new123pass").await.success);
+
+        // Codes can be used once, the other ones reset the new password
+        assert!(!db.recover_account(&user_name, &code, "other123pass").await.success);
+        let code = account.recovery_codes[1].clone();
+        assert!(db.recover_account(&user_name, &code, "other123pass").await.success);
+        assert!(db.login(&user_name, "other123pass").await.success);
+    }
+
+    #[test]
+    fn user_name_and_password_validation() {
+        let rules = ValidationRules::default();
+
+        assert_eq!(
+            rules.validate_user_name("ab"),
+            Err(ValidationErrors(vec![ValidationError::TooShort {
+                field: Field::UserName,
+                min: 3,
+                length: 2
+            }]))
+        );
+        assert_eq!(
+            rules.validate_user_name("ali\nce"),
+            Err(ValidationErrors(vec![ValidationError::ControlCharacter {
+                field: Field::UserName,
+                position: 3
+            }]))
+        );
+        // Cyrillic "а" looks like the latin one
+        assert!(rules.validate_user_name("p\u{430}ypal").is_err());
+        assert_eq!(
+            rules.validate_user_name("jose\u{301}"),
+            Ok("jos\u{e9}".to_string())
+        );
+
+        assert_eq!(password_strength("1234", None), PasswordStrength::VeryWeak);
+        assert_eq!(password_strength("fake123pass", None), PasswordStrength::Fair);
+        assert_eq!(
+            password_strength("Correct-Horse-42", None),
+            PasswordStrength::VeryStrong
+        );
+
+        let errors = rules.validate_account("", "1234").unwrap_err();
+        assert_eq!(errors.0[0], ValidationError::Empty { field: Field::UserName });
+        assert!(errors.0.iter().any(|error| matches!(
+            error,
+            ValidationError::TooShort {
+                field: Field::Password,
+                ..
+            }
+        )));
+        assert!(rules.validate_account("alice", "alice12345").is_err());
+        assert_eq!(
+            rules.validate_account("alice", "fake123pass"),
+            Ok("alice".to_string())
+        );
+    }
+
+    async fn user_profile_written_by_its_session() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let user_name = random_str();
+        let user_id = db
+            .create_user_account(&user_name, "fake123pass", None, None)
+            .await
+            .data
+            .unwrap()
+            .id;
+
+        assert!(!db.get_profile::<UserProfile>(&user_id).await.success);
+
+        let session = db.login(&user_name, "fake123pass").await.data.unwrap();
+        let updated = session
+            .update_profile(UserProfile::default, |profile| {
+                profile.display_name = Some("Wenderson".to_string());
+                profile.settings.insert("theme".to_string(), "dark".to_string());
+            })
+            .await
+            .data
+            .unwrap();
+
+        let profile = db.get_profile::<UserProfile>(&user_id).await.data.unwrap();
+        assert_eq!(profile, updated);
+        assert_eq!(profile.display_name, Some("Wenderson".to_string()));
+
+        session.logout();
+        let after_logout = session.set_profile(&UserProfile::default()).await;
+        assert_eq!(after_logout.error_msg, "Session was logged out");
+
+        // Moved along with the account
+        let account = db
+            .change_password(&user_name, "fake123pass", "new123pass")
+            .await
+            .data
+            .unwrap();
+        let moved = db.get_profile::<UserProfile>(&account.id).await.data.unwrap();
+        assert_eq!(moved, updated);
+    }
+
+    #[test]
+    fn totp_codes_match_rfc_6238() {
+        // "12345678901234567890" in base32
+        let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+        assert_eq!(totp_code(secret, 59), Some("287082".to_string()));
+        assert_eq!(totp_code(secret, 1111111109), Some("081804".to_string()));
+        assert_eq!(totp_code(secret, 1234567890), Some("005924".to_string()));
+        assert_eq!(totp_code(secret, 2000000000), Some("279037".to_string()));
+        assert_eq!(totp_code("not base32!", 59), None);
+    }
+
+    async fn two_factor_login_with_totp_and_backup_codes() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let user_name = random_str();
+        let _ = db
+            .create_user_account(&user_name, "fake123pass", Some(Units::new(10)), None)
+            .await;
+
+        let session = db.login(&user_name, "fake123pass").await.data.unwrap();
+        assert!(!session.needs_two_factor());
+
+        let enrollment = session.enroll_two_factor("Chain DB/Test").await.data.unwrap();
+        assert!(enrollment.uri.starts_with("otpauth://totp/Chain%20DB%2FTest:"));
+        assert!(!session.confirm_two_factor("000000").await.success);
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let code = totp_code(&enrollment.secret, now).unwrap();
+        let backup_codes = session.confirm_two_factor(&code).await.data.unwrap();
+        assert_eq!(backup_codes.len(), 8);
+
+        let pending = db.login(&user_name, "fake123pass").await.data.unwrap();
+        assert!(pending.needs_two_factor());
+        assert!(!pending.is_active());
+        let blocked = pending.transfers();
+        assert_eq!(blocked.error_msg, "Two-factor verification is required");
+
+        // Already used to confirm the enrollment
+        assert!(!pending.verify_two_factor(&code).await.success);
+        assert!(pending.verify_two_factor(&backup_codes[0]).await.success);
+        assert!(pending.is_active());
+
+        let other = db.login(&user_name, "fake123pass").await.data.unwrap();
+        assert!(!other.verify_two_factor(&backup_codes[0]).await.success);
+        assert!(other.verify_two_factor(&backup_codes[1]).await.success);
+
+        assert!(other.disable_two_factor(&backup_codes[2]).await.success);
+        let after_disable = db.login(&user_name, "fake123pass").await.data.unwrap();
+        assert!(!after_disable.needs_two_factor());
+
+        let enrollment = other.enroll_two_factor("Chain DB/Test").await.data.unwrap();
+        let code = totp_code(&enrollment.secret, now).unwrap();
+        let backup_codes = other.confirm_two_factor(&code).await.data.unwrap();
+
+        // Guessing codes locks the account and logs out the pending session
+        let guessed = db.login(&user_name, "fake123pass").await.data.unwrap();
+        for _ in 0..5 {
+            assert!(!guessed.verify_two_factor("000000").await.success);
+        }
+        let locked = guessed.verify_two_factor(&backup_codes[0]).await;
+        assert_eq!(locked.error_msg, "Session was logged out");
+
+        // Logging in again doesn't reset the failures
+        let again = db.login(&user_name, "fake123pass").await.data.unwrap();
+        let still_locked = again.verify_two_factor(&backup_codes[0]).await;
+        assert!(still_locked.error_msg.starts_with("Too many invalid two-factor codes"));
+    }
+
+    async fn login_throttle_delays_and_locks_out() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let user_name = random_str();
+        let _ = db
+            .create_user_account(&user_name, "fake123pass", None, None)
+            .await;
+
+        let throttle = db.login_throttle(ThrottleOptions::default());
+        assert!(!throttle.login(&user_name, "wrong", Some("10.0.0.1")).await.success);
+        let delayed = throttle.login(&user_name, "fake123pass", None).await;
+        assert!(delayed.error_msg.starts_with("Too many failed logins"));
+        assert!(throttle.retry_after(&user_name, None).await.is_some());
+        // The source is delayed for any user name
+        assert!(throttle.retry_after(&random_str(), Some("10.0.0.1")).await.is_some());
+        assert!(throttle.retry_after(&random_str(), Some("10.0.0.2")).await.is_none());
+
+        // A valid login doesn't leave its source delayed
+        let other_user = random_str();
+        let _ = db
+            .create_user_account(&other_user, "fake123pass", None, None)
+            .await;
+        assert!(throttle.login(&other_user, "fake123pass", Some("10.0.0.2")).await.success);
+        assert!(throttle.retry_after(&random_str(), Some("10.0.0.2")).await.is_none());
+
+        let lockout = db.login_throttle(ThrottleOptions {
+            base_delay: std::time::Duration::ZERO,
+            lockout_threshold: 3,
+            ..ThrottleOptions::default()
+        });
+        for _ in 0..3 {
+            let failed = lockout.get_user_account(&user_name, "wrong", None).await;
+            assert!(!failed.error_msg.starts_with("Too many failed logins"));
+        }
+        let locked = lockout.login(&user_name, "fake123pass", None).await;
+        assert!(!locked.success);
+        assert!(lockout.retry_after(&user_name, None).await.unwrap().as_secs() > 60);
+
+        lockout.reset(&user_name).await;
+        assert!(lockout.login(&user_name, "fake123pass", None).await.success);
+    }
+
+    #[test]
+    fn session_tokens_are_signed_and_expire() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let claims = SessionClaims {
+            sub: "b2e4e7c15f733d8c".to_string(),
+            user_name: "wenderson.fake".to_string(),
+            iat: now,
+            exp: now + 60,
+        };
+
+        let session_token = token::issue(&db, &claims);
+        assert_eq!(db.verify_session_token(&session_token).data, Some(claims.clone()));
+
+        // Signed by another database
+        let other_db = ChainDB::connect(None, "other-db", "root", "1234");
+        let other_token = token::issue(&other_db, &claims);
+        assert_eq!(
+            db.verify_session_token(&other_token).error_msg,
+            "Invalid session token"
+        );
+
+        // Claims changed after signing
+        let other_claims = token::issue(
+            &db,
+            &SessionClaims {
+                sub: "someone-else".to_string(),
+                ..claims.clone()
+            },
+        );
+        let parts: Vec<&str> = session_token.split('.').collect();
+        let other_parts: Vec<&str> = other_claims.split('.').collect();
+        let forged = format!("{}.{}.{}", parts[0], other_parts[1], parts[2]);
+        assert!(!db.verify_session_token(&forged).success);
+        assert!(!db.verify_session_token("not-a-token").success);
+
+        let expired = token::issue(
+            &db,
+            &SessionClaims {
+                exp: now - 1,
+                ..claims
+            },
+        );
+        assert_eq!(
+            db.verify_session_token(&expired).error_msg,
+            "Session token has expired"
+        );
+    }
+
+    async fn session_token_resumes_the_user_session() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let user_name = random_str();
+        let _ = db
+            .create_user_account(&user_name, "fake123pass", Some(Units::new(10)), None)
+            .await;
+        let user_id_2 = db
+            .create_user_account(random_str().as_str(), "fake123pass", None, None)
+            .await
+            .data
+            .unwrap()
+            .id;
+
+        let session = db.login(&user_name, "fake123pass").await.data.unwrap();
+        let session_token = session.issue_token().data.unwrap();
+
+        let claims = db.verify_session_token(&session_token).data.unwrap();
+        assert_eq!(claims.sub, session.user_id());
+        assert_eq!(claims.exp, session.expires_at());
+
+        let resumed = db.resume_session(&session_token).await.data.unwrap();
+        assert_eq!(resumed.user_id(), session.user_id());
+        assert!(resumed.transfer(&user_id_2, Units::new(1), None).await.success);
+
+        session.logout();
+        assert!(!session.issue_token().success);
+    }
+
+    #[test]
+    fn kv_namespace_with_separator_is_rejected() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let error = db.kv("feature:flags").unwrap_err();
+        assert!(error.starts_with("Invalid key-value namespace"));
+        assert!(db.kv("").is_err());
+        assert!(db.escrow("escrow/account").is_err());
+    }
+
+    async fn transaction_rejects_tables_changed_since_read() {
+        let db = ChainDB::connect(None, "test-db", "root", "1234");
+        let mut stale = db.get_table("tx-orders", TestTable::new).await;
+        let mut newer = db.get_table("tx-orders", TestTable::new).await;
+
+        newer.table.set_greeting(random_str());
+        newer.persist().await;
+
+        // Nothing is written if any table was changed
+        let mut inventory = db.get_table("tx-inventory", TestTable::new).await;
+        let inventory_year = inventory.table.year;
+        inventory.table.year = inventory_year.wrapping_add(1);
+
+        stale.table.set_greeting("Stale".to_string());
+        let commit = db
+            .transaction()
+            .stage(&inventory)
+            .stage(&stale)
+            .commit()
+            .await;
+        assert!(!commit.success);
+
+        let orders = db.get_table("tx-orders", TestTable::new).await;
+        assert_eq!(orders.table.greeting, newer.table.greeting);
+        let inventory = db.get_table("tx-inventory", TestTable::new).await;
+        assert_eq!(inventory.table.year, inventory_year);
+        assert!(db.recover_transactions().await.success);
+    }
+
+    #[tokio::test]
+    async fn scheduler_keeps_running_when_the_server_is_unreachable() {
+        let db = ChainDB::connect(Some("http://127.0.0.1:1"), "test-db", "root", "1234");
+        let scheduler = db
+            .transfer_scheduler()
+            .with_poll_interval(std::time::Duration::from_millis(10));
+
+        let task = scheduler.start();
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(!task.is_finished());
+        task.abort();
+    }
+
     #[tokio::test]
     async fn integration_all_features() {
         create_user_account().await;
@@ -457,6 +2243,30 @@ mod tests {
         get_all_user_tranfer_records().await;
         create_table_and_write_read_data().await;
         get_table_history().await;
+        key_value_store_set_get_delete().await;
+        append_log_read_tail_and_stream().await;
+        aggregate_execute_replay_and_snapshot().await;
+        transaction_commits_several_tables().await;
+        transfer_units_and_persist_table().await;
+        transfer_named_assets_between_two_users().await;
+        transfer_batch_with_all_or_nothing().await;
+        transfer_units_with_id_is_idempotent().await;
+        transfer_records_with_memo_and_transfer_id().await;
+        query_transfer_records_with_filters_and_pages().await;
+        account_statement_with_running_balances().await;
+        reconcile_accounts_after_transfers().await;
+        escrow_hold_release_and_refund().await;
+        scheduled_and_recurring_transfers().await;
+        multisig_transfer_needs_approvals().await;
+        transfer_policies_are_evaluated_before_transfers().await;
+        user_session_transfers_tables_and_logout().await;
+        change_password_migrates_units_and_tables().await;
+        password_hint_and_account_recovery().await;
+        user_profile_written_by_its_session().await;
+        two_factor_login_with_totp_and_backup_codes().await;
+        login_throttle_delays_and_locks_out().await;
+        session_token_resumes_the_user_session().await;
+        transaction_rejects_tables_changed_since_read().await;
     }
 
 }
\ No newline at end of file
@@ -18,3 +18,46 @@ impl TestTable {
         self.greeting = greeting;
     }
 }
+
+#[cfg(test)]
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CounterAggregate {
+    pub value: u64,
+}
+
+#[cfg(test)]
+#[derive(Serialize, Deserialize, Debug)]
+pub enum CounterEvent {
+    Added(u64),
+    Removed(u64),
+}
+
+#[cfg(test)]
+pub enum CounterCommand {
+    Add(u64),
+    Remove(u64),
+}
+
+#[cfg(test)]
+impl crate::Aggregate for CounterAggregate {
+    type Event = CounterEvent;
+    type Command = CounterCommand;
+    type Error = String;
+
+    fn apply(&mut self, event: &CounterEvent) {
+        match event {
+            CounterEvent::Added(amount) => self.value += amount,
+            CounterEvent::Removed(amount) => self.value -= amount,
+        }
+    }
+
+    fn handle(&self, command: CounterCommand) -> Result<Vec<CounterEvent>, String> {
+        match command {
+            CounterCommand::Add(amount) => Ok(vec![CounterEvent::Added(amount)]),
+            CounterCommand::Remove(amount) if amount > self.value => {
+                Err("Counter can not be negative".to_string())
+            }
+            CounterCommand::Remove(amount) => Ok(vec![CounterEvent::Removed(amount)]),
+        }
+    }
+}